@@ -0,0 +1,219 @@
+//! Models offered by the configured provider.
+//!
+//! The list comes from the provider's models endpoint (`/v1/models` for OpenAI,
+//! `/models` for OpenRouter) and is cached in `models-<provider>.json` in the cache
+//! directory for `models_cache_ttl` hours.
+
+use crate::config_manager::{cache_dir, Config, ModelPrice, Provider};
+use crate::error::{api_err, validation_err, Result};
+use reqwest::header::AUTHORIZATION;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// A model offered by a provider
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelInfo {
+    /// Name to use as the model in requests
+    pub id: String,
+
+    /// Context window in tokens, if the provider reports it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_length: Option<usize>,
+
+    /// Price in US dollars per million tokens, if the provider reports it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pricing: Option<ModelPrice>,
+}
+
+/// Body of a models endpoint response
+#[derive(Debug, Deserialize)]
+struct ModelsResponse {
+    data: Vec<ModelEntry>,
+}
+
+/// A model in a models endpoint response
+///
+/// OpenAI only returns the id; OpenRouter adds the context length and prices per
+/// token as decimal strings.
+#[derive(Debug, Deserialize)]
+struct ModelEntry {
+    id: String,
+    #[serde(default)]
+    context_length: Option<usize>,
+    #[serde(default)]
+    pricing: Option<PricingEntry>,
+}
+
+/// Prices per token as reported by OpenRouter
+#[derive(Debug, Deserialize)]
+struct PricingEntry {
+    prompt: String,
+    completion: String,
+}
+
+impl PricingEntry {
+    /// Convert to prices per million tokens, if both prices are valid numbers
+    fn per_million(&self) -> Option<ModelPrice> {
+        Some(ModelPrice {
+            prompt: self.prompt.parse::<f64>().ok()? * 1_000_000.0,
+            completion: self.completion.parse::<f64>().ok()? * 1_000_000.0,
+        })
+    }
+}
+
+/// The models offered by a provider at the time they were fetched
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelCatalog {
+    /// Provider the list came from
+    pub provider: Provider,
+
+    /// When the list was fetched, in seconds since the Unix epoch
+    pub fetched_at: i64,
+
+    /// Models sorted by id
+    pub models: Vec<ModelInfo>,
+}
+
+impl ModelCatalog {
+    /// Get the catalog for the configured provider, from the cache while it is fresh
+    pub async fn load(config: &Config, refresh: bool) -> Result<Self> {
+        if !refresh {
+            if let Some(catalog) = Self::cached(&config.provider) {
+                let age = chrono::Utc::now().timestamp() - catalog.fetched_at;
+                if (0..config.models_cache_ttl as i64 * 3600).contains(&age) {
+                    return Ok(catalog);
+                }
+            }
+        }
+
+        let catalog = Self::fetch(config).await?;
+        if let Err(e) = catalog.save() {
+            log::warn!("Failed to cache the list of models: {}", e);
+        }
+
+        Ok(catalog)
+    }
+
+    /// Get the cached catalog for a provider, however old it is
+    pub fn cached(provider: &Provider) -> Option<Self> {
+        let contents = std::fs::read_to_string(cache_path(provider)).ok()?;
+        serde_json::from_str(&contents).ok()
+    }
+
+    /// Fetch the catalog from the provider's models endpoint
+    pub async fn fetch(config: &Config) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(30))
+            .build()
+            .map_err(|e| api_err(format!("Failed to create HTTP client: {}", e)))?;
+
+        let mut request = client.get(config.get_models_url());
+        let api_key = config.get_api_key();
+        if !api_key.is_empty() {
+            request = request.header(AUTHORIZATION, format!("Bearer {}", api_key));
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| api_err(format!("Failed to list models: {}", e)))?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "Unknown error".to_string());
+            return Err(api_err(format!(
+                "Listing models returned error ({}): {}",
+                status, error_text
+            )));
+        }
+
+        let body: ModelsResponse = response
+            .json()
+            .await
+            .map_err(|e| api_err(format!("Failed to parse the list of models: {}", e)))?;
+
+        let mut models: Vec<ModelInfo> = body
+            .data
+            .into_iter()
+            .map(|entry| ModelInfo {
+                pricing: entry.pricing.as_ref().and_then(PricingEntry::per_million),
+                context_length: entry.context_length,
+                id: entry.id,
+            })
+            .collect();
+        models.sort_by(|a, b| a.id.cmp(&b.id));
+
+        Ok(Self {
+            provider: config.provider.clone(),
+            fetched_at: chrono::Utc::now().timestamp(),
+            models,
+        })
+    }
+
+    /// Write the catalog to the cache
+    fn save(&self) -> Result<()> {
+        let path = cache_path(&self.provider);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Look up a model by id
+    pub fn get(&self, id: &str) -> Option<&ModelInfo> {
+        self.models.iter().find(|model| model.id == id)
+    }
+
+    /// Ids of all models
+    pub fn ids(&self) -> impl Iterator<Item = &str> {
+        self.models.iter().map(|model| model.id.as_str())
+    }
+
+    /// Check that a model is offered, suggesting similar ids when it isn't
+    pub fn check(&self, id: &str) -> Result<()> {
+        if self.get(id).is_some() {
+            return Ok(());
+        }
+
+        let suggestions = self.suggestions(id);
+        let hint = if suggestions.is_empty() {
+            String::new()
+        } else {
+            format!(" Did you mean '{}'?", suggestions.join("', '"))
+        };
+        Err(validation_err(format!(
+            "Unknown model '{}' for {}.{} Run `chris models` to list available models.",
+            id, self.provider, hint
+        )))
+    }
+
+    /// Ids similar to `id`, closest first
+    ///
+    /// Ids are compared with and without the vendor prefix, so `gpt-4o` suggests
+    /// `openai/gpt-4o` on OpenRouter.
+    pub fn suggestions(&self, id: &str) -> Vec<&str> {
+        let wanted = id.to_lowercase();
+        let mut scored: Vec<(f64, &str)> = self
+            .ids()
+            .map(|candidate| {
+                let lower = candidate.to_lowercase();
+                let base = lower.rsplit('/').next().unwrap_or(&lower);
+                let score =
+                    strsim::jaro_winkler(&wanted, &lower).max(strsim::jaro_winkler(&wanted, base));
+                (score, candidate)
+            })
+            .filter(|(score, _)| *score >= 0.85)
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored.into_iter().take(3).map(|(_, id)| id).collect()
+    }
+}
+
+/// Location of the cached catalog for a provider
+fn cache_path(provider: &Provider) -> PathBuf {
+    cache_dir().join(format!("models-{}.json", provider.as_str()))
+}
@@ -1,15 +1,38 @@
-use crate::api_client::models::{CompletionRequest, CompletionResponse, Message};
+use crate::api_client::models::{
+    Completion, CompletionRequest, CompletionResponse, Message, PreparedMessages,
+};
+use crate::api_client::tokens;
 use crate::config_manager::{Config, Provider};
-use crate::error::{api_err, Result};
-use crate::personalization::UserContext;
+use crate::error::{api_err, validation_err, Result};
+use crate::personalization::{prompt_variables, UserContext};
+use crate::template;
 use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
 use std::time::Duration;
 
+/// System prompt used when none is configured
+pub const DEFAULT_SYSTEM_PROMPT: &str = "You are Chris, a helpful AI assistant.";
+
 /// Trait defining the interface for API clients
 #[async_trait::async_trait]
 pub trait ApiClientTrait {
-    /// Send a query to the model
-    async fn send_query(&self, query: &str, user_context: Option<&UserContext>) -> Result<String>;
+    /// Send a query to the model, following the earlier turns of the conversation
+    async fn send_query(
+        &self,
+        history: &[Message],
+        query: &str,
+        user_context: Option<&UserContext>,
+    ) -> Result<Completion>;
+    /// Build the messages for a query, leaving out the oldest turns that don't fit
+    fn prepare_messages(
+        &self,
+        history: &[Message],
+        query: &str,
+        user_context: Option<&UserContext>,
+    ) -> Result<PreparedMessages>;
+    /// Send messages built by `prepare_messages`
+    async fn send_messages(&self, messages: Vec<Message>) -> Result<Completion>;
+    /// Get the system prompt sent with each query, with its template variables filled in
+    fn system_prompt(&self, user_context: Option<&UserContext>) -> Result<String>;
     /// Get a reference to the configuration
     fn config(&self) -> &Config;
 }
@@ -18,7 +41,7 @@ pub trait ApiClientTrait {
 #[async_trait::async_trait]
 pub trait ModelClient: Send + Sync {
     /// Send a request to the model and get a response
-    async fn send_request(&self, messages: Vec<Message>, config: &Config) -> Result<String>;
+    async fn send_request(&self, messages: Vec<Message>, config: &Config) -> Result<Completion>;
 }
 
 /// HTTP-based model client implementation
@@ -40,7 +63,7 @@ impl HttpModelClient {
 
 #[async_trait::async_trait]
 impl ModelClient for HttpModelClient {
-    async fn send_request(&self, messages: Vec<Message>, config: &Config) -> Result<String> {
+    async fn send_request(&self, messages: Vec<Message>, config: &Config) -> Result<Completion> {
         let response = match config.provider {
             Provider::OpenAI => self.send_openai_request(messages, config).await?,
             Provider::OpenRouter => self.send_openrouter_request(messages, config).await?,
@@ -63,11 +86,17 @@ impl ModelClient for HttpModelClient {
             .await
             .map_err(|e| api_err(format!("Failed to parse API response: {}", e)))?;
 
-        completion
+        let content = completion
             .choices
             .first()
             .map(|choice| choice.message.content.clone())
-            .ok_or_else(|| api_err("API returned no completion choices"))
+            .ok_or_else(|| api_err("API returned no completion choices"))?;
+
+        Ok(Completion {
+            content,
+            model: config.model().to_string(),
+            usage: completion.usage,
+        })
     }
 }
 
@@ -89,7 +118,12 @@ impl HttpModelClient {
             model: config.openai_model.clone(),
             messages,
             max_tokens: config.max_tokens,
-            temperature: 0.7,
+            temperature: config.temperature,
+            top_p: config.top_p,
+            stop: config.stop.clone(),
+            seed: config.seed,
+            presence_penalty: config.presence_penalty,
+            frequency_penalty: config.frequency_penalty,
             http_referer: None,
             http_referrer: None,
         };
@@ -125,7 +159,12 @@ impl HttpModelClient {
             model: config.openrouter_model.clone(),
             messages,
             max_tokens: config.max_tokens,
-            temperature: 0.7,
+            temperature: config.temperature,
+            top_p: config.top_p,
+            stop: config.stop.clone(),
+            seed: config.seed,
+            presence_penalty: config.presence_penalty,
+            frequency_penalty: config.frequency_penalty,
             http_referer: Some(config.get_site_url()),
             http_referrer: Some(config.get_site_url()),
         };
@@ -152,34 +191,93 @@ impl<T: ModelClient> BaseApiClient<T> {
         Self { config, client }
     }
 
-    fn create_messages(&self, query: &str, user_context: Option<&UserContext>) -> Vec<Message> {
-        let mut messages = Vec::new();
+    fn system_prompt(&self, user_context: Option<&UserContext>) -> Result<String> {
+        let base_prompt = template::render(
+            self.config
+                .system_prompt
+                .as_deref()
+                .unwrap_or(DEFAULT_SYSTEM_PROMPT),
+            &prompt_variables(user_context),
+        )?;
 
-        // Add system message
-        let system_content = if let Some(context) = user_context {
+        Ok(if let Some(context) = user_context {
             format!(
-                "You are Chris, a helpful AI assistant. You are talking to {} who is using {} {} with kernel version {}. \
+                "{} You are talking to {} who is using {} {} with kernel version {}. \
                 Always provide responses specific to their operating system and environment. \
                 If you need additional system information, ask the user or suggest specific commands they can run. \
                 Never provide instructions for other operating systems unless explicitly asked.",
-                context.username, context.os_name, context.os_version, context.kernel_version
+                base_prompt, context.username, context.os_name, context.os_version, context.kernel_version
             )
         } else {
-            "You are Chris, a helpful AI assistant.".to_string()
-        };
+            base_prompt
+        })
+    }
 
-        messages.push(Message {
-            role: "system".to_string(),
-            content: system_content,
-        });
+    /// Build the messages for a query
+    ///
+    /// The system prompt and the query are always sent. Earlier turns are added from
+    /// the most recent backwards for as long as they fit in the context window with
+    /// room left for `max_tokens` of response; older ones are dropped whole, so the
+    /// conversation never starts with a response to a query that was left out.
+    fn create_messages(
+        &self,
+        history: &[Message],
+        query: &str,
+        user_context: Option<&UserContext>,
+    ) -> Result<PreparedMessages> {
+        let model = self.config.model();
+        let context_window = tokens::context_window(model, &self.config.context_windows);
+        let budget = context_window.saturating_sub(self.config.max_tokens);
 
-        // Add user message
-        messages.push(Message {
+        let system = Message {
+            role: "system".to_string(),
+            content: self.system_prompt(user_context)?,
+        };
+        let user = Message {
             role: "user".to_string(),
             content: query.to_string(),
-        });
+        };
+
+        let mut prompt_tokens = tokens::count_messages(model, [&system, &user]);
+        if prompt_tokens > budget {
+            return Err(validation_err(format!(
+                "The prompt is about {} tokens, but {} only has room for {} \
+                ({} token context window minus max_tokens of {})",
+                prompt_tokens, model, budget, context_window, self.config.max_tokens
+            )));
+        }
+
+        let counts: Vec<usize> = history
+            .iter()
+            .map(|message| tokens::count_message(model, message))
+            .collect();
+
+        let mut start = history.len();
+        while start > 0 && prompt_tokens + counts[start - 1] <= budget {
+            start -= 1;
+            prompt_tokens += counts[start];
+        }
+        while start < history.len() && history[start].role == "assistant" {
+            prompt_tokens -= counts[start];
+            start += 1;
+        }
+
+        let dropped = &history[..start];
+        let dropped_turns = dropped.iter().filter(|m| m.role == "user").count();
+        let dropped_tokens = counts[..start].iter().sum();
 
-        messages
+        let mut messages = Vec::with_capacity(history.len() - start + 2);
+        messages.push(system);
+        messages.extend_from_slice(&history[start..]);
+        messages.push(user);
+
+        Ok(PreparedMessages {
+            messages,
+            prompt_tokens,
+            context_window,
+            dropped_turns,
+            dropped_tokens,
+        })
     }
 }
 
@@ -191,11 +289,33 @@ pub struct OpenAIClient {
 
 #[async_trait::async_trait]
 impl ApiClientTrait for OpenAIClient {
-    async fn send_query(&self, query: &str, user_context: Option<&UserContext>) -> Result<String> {
-        let messages = self.base.create_messages(query, user_context);
+    async fn send_query(
+        &self,
+        history: &[Message],
+        query: &str,
+        user_context: Option<&UserContext>,
+    ) -> Result<Completion> {
+        let prepared = self.prepare_messages(history, query, user_context)?;
+        self.send_messages(prepared.messages).await
+    }
+
+    fn prepare_messages(
+        &self,
+        history: &[Message],
+        query: &str,
+        user_context: Option<&UserContext>,
+    ) -> Result<PreparedMessages> {
+        self.base.create_messages(history, query, user_context)
+    }
+
+    async fn send_messages(&self, messages: Vec<Message>) -> Result<Completion> {
         self.base.client.send_request(messages, self.config()).await
     }
 
+    fn system_prompt(&self, user_context: Option<&UserContext>) -> Result<String> {
+        self.base.system_prompt(user_context)
+    }
+
     fn config(&self) -> &Config {
         &self.base.config
     }
@@ -209,11 +329,33 @@ pub struct OpenRouterClient {
 
 #[async_trait::async_trait]
 impl ApiClientTrait for OpenRouterClient {
-    async fn send_query(&self, query: &str, user_context: Option<&UserContext>) -> Result<String> {
-        let messages = self.base.create_messages(query, user_context);
+    async fn send_query(
+        &self,
+        history: &[Message],
+        query: &str,
+        user_context: Option<&UserContext>,
+    ) -> Result<Completion> {
+        let prepared = self.prepare_messages(history, query, user_context)?;
+        self.send_messages(prepared.messages).await
+    }
+
+    fn prepare_messages(
+        &self,
+        history: &[Message],
+        query: &str,
+        user_context: Option<&UserContext>,
+    ) -> Result<PreparedMessages> {
+        self.base.create_messages(history, query, user_context)
+    }
+
+    async fn send_messages(&self, messages: Vec<Message>) -> Result<Completion> {
         self.base.client.send_request(messages, self.config()).await
     }
 
+    fn system_prompt(&self, user_context: Option<&UserContext>) -> Result<String> {
+        self.base.system_prompt(user_context)
+    }
+
     fn config(&self) -> &Config {
         &self.base.config
     }
@@ -221,6 +363,7 @@ impl ApiClientTrait for OpenRouterClient {
 
 /// Factory function to create the appropriate API client based on the provider
 pub fn create_api_client(config: Config) -> Result<Box<dyn ApiClientTrait>> {
+    config.validate()?;
     let http_client = HttpModelClient::new()?;
 
     match config.provider {
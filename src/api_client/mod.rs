@@ -3,9 +3,15 @@
 //! This module provides functionality to communicate with various GPT
 //! model providers like OpenAI and OpenRouter.
 
+mod catalog;
 mod client;
 mod models;
+pub mod tokens;
 
 // Re-exports for public API
-pub use client::{create_api_client, ApiClient, ApiClientTrait, OpenAIClient, OpenRouterClient};
-pub use models::Message;
+pub use catalog::{ModelCatalog, ModelInfo};
+pub use client::{
+    create_api_client, ApiClient, ApiClientTrait, OpenAIClient, OpenRouterClient,
+    DEFAULT_SYSTEM_PROMPT,
+};
+pub use models::{Completion, Message, PreparedMessages, Usage};
@@ -23,7 +23,27 @@ pub(crate) struct CompletionRequest {
     pub max_tokens: usize,
 
     /// Temperature parameter for controlling randomness
-    pub temperature: f32,
+    pub temperature: f64,
+
+    /// Nucleus sampling probability mass
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+
+    /// Sequences where generation stops
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+
+    /// Seed for best-effort deterministic sampling
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+
+    /// Penalty for tokens that already appeared
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f64>,
+
+    /// Penalty proportional to how often tokens appeared
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f64>,
 
     /// Referer header for OpenRouter (http_referer variant)
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -39,6 +59,54 @@ pub(crate) struct CompletionRequest {
 pub(crate) struct CompletionResponse {
     /// Array of completion choices
     pub choices: Vec<Choice>,
+
+    /// Token counts for the request, if the provider reports them
+    #[serde(default)]
+    pub usage: Option<Usage>,
+}
+
+/// Token counts reported for a request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Usage {
+    /// Tokens in the prompt, including the system prompt and history
+    #[serde(default)]
+    pub prompt_tokens: u64,
+
+    /// Tokens generated in the completion
+    #[serde(default)]
+    pub completion_tokens: u64,
+}
+
+/// A completion returned by the model
+#[derive(Debug, Clone)]
+pub struct Completion {
+    /// Text of the completion
+    pub content: String,
+
+    /// Model the request was sent to
+    pub model: String,
+
+    /// Token counts, if the provider reported them
+    pub usage: Option<Usage>,
+}
+
+/// Messages ready to be sent, after fitting the conversation into the context window
+#[derive(Debug, Clone)]
+pub struct PreparedMessages {
+    /// System prompt, the history that fits and the new query
+    pub messages: Vec<Message>,
+
+    /// Estimated tokens in `messages`
+    pub prompt_tokens: usize,
+
+    /// Context window of the model
+    pub context_window: usize,
+
+    /// Number of earlier turns left out to make room
+    pub dropped_turns: usize,
+
+    /// Estimated tokens in the turns that were left out
+    pub dropped_tokens: usize,
 }
 
 /// Represents a single completion choice
@@ -0,0 +1,95 @@
+//! Token counting and context window sizes.
+//!
+//! Counts use the tokenizer of the OpenAI model family when one is known. Other
+//! models (Claude, Llama, DeepSeek, ...) use `cl100k_base`, which is close enough to
+//! decide what fits in the context window.
+
+use crate::api_client::models::Message;
+use std::collections::BTreeMap;
+use tiktoken_rs::tokenizer::{get_tokenizer, Tokenizer};
+use tiktoken_rs::CoreBPE;
+
+/// Tokens added by the chat format for every message
+const TOKENS_PER_MESSAGE: usize = 3;
+
+/// Tokens added by the chat format to prime the reply
+const TOKENS_PER_REPLY: usize = 3;
+
+/// Context window used for models missing from [`CONTEXT_WINDOWS`]
+pub const DEFAULT_CONTEXT_WINDOW: usize = 8_192;
+
+/// Context window sizes by model name prefix, most specific first
+///
+/// Names are matched without the vendor prefix used by OpenRouter
+/// (e.g. `openai/gpt-4o` is looked up as `gpt-4o`).
+const CONTEXT_WINDOWS: &[(&str, usize)] = &[
+    ("gpt-4.1", 1_047_576),
+    ("gpt-4o", 128_000),
+    ("gpt-4-turbo", 128_000),
+    ("gpt-4-32k", 32_768),
+    ("gpt-4", 8_192),
+    ("gpt-3.5-turbo-instruct", 4_096),
+    ("gpt-3.5-turbo", 16_385),
+    ("o1-mini", 128_000),
+    ("o1", 200_000),
+    ("o3", 200_000),
+    ("o4-mini", 200_000),
+    ("claude", 200_000),
+    ("gemini", 1_000_000),
+    ("deepseek", 64_000),
+    ("llama-3.1", 128_000),
+    ("llama-3.2", 128_000),
+    ("llama-3.3", 128_000),
+    ("llama-3", 8_192),
+    ("mistral", 32_000),
+    ("mixtral", 32_000),
+    ("qwen", 32_000),
+];
+
+/// Model name without the vendor prefix used by OpenRouter
+fn base_name(model: &str) -> &str {
+    model.rsplit('/').next().unwrap_or(model)
+}
+
+/// Tokenizer for a model
+fn tokenizer(model: &str) -> &'static CoreBPE {
+    match get_tokenizer(base_name(model)) {
+        Some(Tokenizer::O200kBase) => tiktoken_rs::o200k_base_singleton(),
+        _ => tiktoken_rs::cl100k_base_singleton(),
+    }
+}
+
+/// Number of tokens in a piece of text
+pub fn count_text(model: &str, text: &str) -> usize {
+    tokenizer(model).encode_with_special_tokens(text).len()
+}
+
+/// Number of tokens a message takes up in a request
+pub fn count_message(model: &str, message: &Message) -> usize {
+    TOKENS_PER_MESSAGE + count_text(model, &message.role) + count_text(model, &message.content)
+}
+
+/// Number of tokens a list of messages takes up in a request, including the reply primer
+pub fn count_messages<'a>(model: &str, messages: impl IntoIterator<Item = &'a Message>) -> usize {
+    TOKENS_PER_REPLY
+        + messages
+            .into_iter()
+            .map(|message| count_message(model, message))
+            .sum::<usize>()
+}
+
+/// Context window of a model, in tokens
+///
+/// Entries in `overrides` are matched against the full model name and take
+/// precedence over the built-in table.
+pub fn context_window(model: &str, overrides: &BTreeMap<String, usize>) -> usize {
+    if let Some(window) = overrides.get(model) {
+        return *window;
+    }
+
+    let name = base_name(model);
+    CONTEXT_WINDOWS
+        .iter()
+        .find(|(prefix, _)| name.starts_with(prefix))
+        .map_or(DEFAULT_CONTEXT_WINDOW, |(_, window)| *window)
+}
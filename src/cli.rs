@@ -1,13 +1,18 @@
-use crate::api_client::{create_api_client, ApiClient};
-// use crate::config_manager::Config;
-use crate::error::Result;
+use crate::api_client::{create_api_client, ApiClient, Completion, Message, ModelCatalog};
+use crate::code_blocks;
+use crate::command_menu::CommandMenu;
+use crate::config_manager::{Config, ConfigDocument, ConfigSource, CredentialBackend, Provider};
+use crate::credentials;
+use crate::error::{config_err, credential_err, Result};
 use crate::personalization::Personalization;
+use crate::prompts::{self, PromptLibrary};
+use crate::repl::{CommandRegistry, Repl, ReplOptions};
+use crate::usage::{current_month, Summary, Totals, UsageLedger, UsageRecord};
 use crate::util;
-use clap::Parser;
-use rustyline::error::ReadlineError;
-use rustyline::DefaultEditor;
-use std::io::Write;
-use std::path::PathBuf;
+use clap::{Parser, Subcommand};
+use std::collections::BTreeMap;
+use std::io::{IsTerminal, Read};
+use std::path::{Path, PathBuf};
 
 /// Enum to track which reader mode we're in
 #[derive(Debug, Clone, Copy)]
@@ -23,6 +28,10 @@ pub enum ReaderMode {
 pub enum Command {
     /// Configuration commands
     Config {
+        /// Configuration action to perform
+        #[clap(subcommand)]
+        action: Option<ConfigAction>,
+
         /// Create a new configuration file
         #[clap(long)]
         create: bool,
@@ -35,6 +44,153 @@ pub enum Command {
         #[clap(short, long, value_name = "FILE")]
         config: Option<PathBuf>,
     },
+
+    /// Send a prompt from the template library
+    Run {
+        /// Name of the template
+        template: String,
+
+        /// Template argument; `input` is read from stdin when it is not given
+        #[clap(long = "arg", value_name = "NAME=VALUE", value_parser = prompts::parse_arg)]
+        args: Vec<(String, String)>,
+
+        /// Print the rendered prompt instead of sending it
+        #[clap(long)]
+        preview: bool,
+    },
+
+    /// List and show prompt templates
+    Templates {
+        /// Template action to perform (defaults to `list`)
+        #[clap(subcommand)]
+        action: Option<TemplateAction>,
+    },
+
+    /// Report token usage and cost, and manage the monthly budget
+    Usage {
+        /// Usage action to perform (defaults to a report)
+        #[clap(subcommand)]
+        action: Option<UsageAction>,
+
+        /// Month to report on, as YYYY-MM (defaults to the current month)
+        #[clap(long, value_name = "YYYY-MM")]
+        month: Option<String>,
+    },
+
+    /// List the models offered by the configured provider
+    Models {
+        /// Only show models whose name contains this text
+        search: Option<String>,
+
+        /// Fetch the list again instead of using the cache
+        #[clap(long)]
+        refresh: bool,
+    },
+
+    /// Manage stored API keys
+    Auth {
+        /// Authentication action to perform
+        #[clap(subcommand)]
+        action: AuthAction,
+    },
+}
+
+/// Actions for the `auth` subcommand
+#[derive(Subcommand, Debug)]
+pub enum AuthAction {
+    /// Store an API key in the credential backend
+    Login {
+        /// Provider the key is for (defaults to the configured provider)
+        #[clap(long)]
+        provider: Option<Provider>,
+
+        /// Credential backend to use (secret-service, encrypted-file); saved to the config
+        #[clap(long)]
+        backend: Option<CredentialBackend>,
+    },
+
+    /// Remove a stored API key
+    Logout {
+        /// Provider the key is for (defaults to the configured provider)
+        #[clap(long)]
+        provider: Option<Provider>,
+    },
+
+    /// Show where each provider's API key comes from
+    Status,
+}
+
+/// Actions for the `templates` subcommand
+#[derive(Subcommand, Debug)]
+pub enum TemplateAction {
+    /// List available templates
+    List,
+
+    /// Show a template's arguments and text
+    Show {
+        /// Name of the template
+        name: String,
+    },
+}
+
+/// Actions for the `usage` subcommand
+#[derive(Subcommand, Debug)]
+pub enum UsageAction {
+    /// Show or set the monthly budget in US dollars
+    Budget {
+        /// Warn before each request once this much has been spent this month
+        #[clap(long, value_name = "USD")]
+        soft: Option<f64>,
+
+        /// Refuse to send requests once this much has been spent this month
+        #[clap(long, value_name = "USD")]
+        hard: Option<f64>,
+
+        /// Remove both limits
+        #[clap(long, conflicts_with_all = ["soft", "hard"])]
+        clear: bool,
+    },
+}
+
+/// Actions for the `config` subcommand
+#[derive(Subcommand, Debug)]
+pub enum ConfigAction {
+    /// Print the value of a configuration key (e.g. `max_tokens` or `profiles.deep.model`)
+    Get {
+        /// Dotted configuration key
+        key: String,
+    },
+
+    /// Set a configuration key, validating the value against the configuration schema
+    Set {
+        /// Dotted configuration key
+        key: String,
+
+        /// New value
+        value: String,
+    },
+
+    /// Remove a key from the configuration file, reverting it to its default
+    Unset {
+        /// Dotted configuration key
+        key: String,
+    },
+
+    /// Open the configuration file in $EDITOR and validate it on save
+    Edit,
+
+    /// Print the path of the configuration file
+    Path,
+
+    /// Print the effective configuration
+    Show {
+        /// Annotate each value with the file, profile, variable or flag that set it
+        #[clap(long)]
+        origin: bool,
+    },
+
+    /// Move files from the legacy ~/.chris directory to their XDG locations
+    Migrate,
 }
 
 /// Chris - A terminal interface for GPT models
@@ -72,6 +228,42 @@ pub struct Cli {
     /// Specify the model to use
     #[clap(long)]
     pub model: Option<String>,
+
+    /// System prompt to use, which may contain template variables such as {{cwd}}
+    #[clap(long, value_name = "PROMPT")]
+    pub system: Option<String>,
+
+    /// Sampling temperature (0 to 2)
+    #[clap(long, value_name = "VALUE")]
+    pub temperature: Option<f64>,
+
+    /// Nucleus sampling probability mass (0 to 1)
+    #[clap(long, value_name = "VALUE")]
+    pub top_p: Option<f64>,
+
+    /// Stop generating at this sequence; may be repeated
+    #[clap(long, value_name = "SEQUENCE")]
+    pub stop: Vec<String>,
+
+    /// Seed for best-effort deterministic sampling
+    #[clap(long)]
+    pub seed: Option<i64>,
+
+    /// Penalty for tokens that already appeared (-2 to 2)
+    #[clap(long, value_name = "VALUE", allow_hyphen_values = true)]
+    pub presence_penalty: Option<f64>,
+
+    /// Penalty proportional to how often tokens appeared (-2 to 2)
+    #[clap(long, value_name = "VALUE", allow_hyphen_values = true)]
+    pub frequency_penalty: Option<f64>,
+
+    /// Configuration profile to use
+    #[clap(short, long, value_name = "NAME")]
+    pub profile: Option<String>,
+
+    /// Save the code blocks of the response as files in this directory
+    #[clap(long, value_name = "DIR")]
+    pub save_code: Option<PathBuf>,
 }
 
 impl Cli {
@@ -85,266 +277,769 @@ impl Cli {
         }
         env_logger::init();
 
+        // Tell users once about configuration left behind by older versions
+        let migrating = matches!(
+            self.command,
+            Some(Command::Config {
+                action: Some(ConfigAction::Migrate),
+                ..
+            })
+        );
+        if !migrating {
+            if let Some(notice) = crate::config_manager::legacy_notice() {
+                eprintln!("{}", notice);
+            }
+        }
+
         // Handle subcommands
         if let Some(command) = &self.command {
             match command {
                 Command::Config {
+                    action,
                     create,
                     show,
                     config,
                 } => {
-                    if *create {
-                        let config_path = config.as_ref().map(|p| p.as_path());
-
-                        // If no path specified, use default
-                        let actual_path = if let Some(path) = config_path {
-                            path.to_path_buf()
-                        } else {
-                            crate::config_manager::Config::get_config_path()
-                        };
-
-                        // Create default config
-                        let default_config = crate::config_manager::Config::default();
-                        default_config.save_to_file(&actual_path)?;
-
-                        println!(
-                            "Created default configuration at: {}",
-                            actual_path.display()
-                        );
-                        return Ok(());
-                    }
-
-                    if *show {
-                        let config_path = config.as_ref().map(|p| p.as_path());
-                        let loaded_config = crate::config_manager::Config::load(config_path)?;
-                        println!("{}", loaded_config);
-                        return Ok(());
-                    }
-
-                    // If neither option was specified
-                    println!("Please specify either --create or --show with the config command");
-                    return Ok(());
+                    return self.run_config_command(
+                        action.as_ref(),
+                        *create,
+                        *show,
+                        config.as_deref().or(self.config.as_deref()),
+                    );
+                }
+                Command::Auth { action } => {
+                    return self.run_auth_command(action).await;
+                }
+                Command::Templates { action } => {
+                    return run_templates_command(action.as_ref());
                 }
+                Command::Usage { action, month } => {
+                    return self.run_usage_command(action.as_ref(), month.as_deref());
+                }
+                Command::Models { search, refresh } => {
+                    return self.run_models_command(search.as_deref(), *refresh).await;
+                }
+                Command::Run { .. } => {}
             }
         }
 
-        // Show configuration if requested
-        if self.show_config {
-            let config_path = self.config.as_deref();
-            let loaded_config = crate::config_manager::Config::load(config_path)?;
-            println!("{}", loaded_config);
-            return Ok(());
-        }
+        // Queries can come from a template instead of the command line
+        let query = match &self.command {
+            Some(Command::Run {
+                template,
+                args,
+                preview,
+            }) => {
+                let prompt = render_template(template, args)?;
+                if *preview {
+                    println!("{}", prompt);
+                    return Ok(());
+                }
+                Some(prompt)
+            }
+            _ => self.query.clone(),
+        };
 
         // Load configuration
         let config_path = self.config.as_deref();
-        let mut config = crate::config_manager::Config::load(config_path)?;
+        let base_config = Config::load(config_path)?;
+        let config = self.resolve_config(&base_config)?;
 
-        // Override config with command line arguments
-        if let Some(model) = &self.model {
-            if config.provider == crate::config_manager::Provider::OpenAI {
-                config.openai_model = model.clone();
-            } else {
-                config.openrouter_model = model.clone();
-            }
+        // Show configuration if requested
+        if self.show_config {
+            println!("{}", config);
+            return Ok(());
         }
 
         // Enable personalization if requested
         let enable_personalization = self.personalize || config.enable_personalization;
 
         // Create API client
-        let api_client = create_api_client(config)?;
+        let api_client = build_api_client(config).await?;
+        if let Some(model) = &self.model {
+            check_model(api_client.config(), model).await?;
+        }
 
         // Create personalization module
         let mut personalization = Personalization::new(enable_personalization);
+        personalization.initialize()?;
 
         // Handle query
-        match &self.query {
+        match &query {
             Some(query) => {
                 self.handle_query(&api_client, query, &mut personalization)
                     .await?;
             }
             None => {
-                self.run_interactive_mode(&api_client, &mut personalization)
-                    .await?;
+                let options = ReplOptions {
+                    debug: self.debug,
+                    verbose: self.verbose,
+                    personalize: self.personalize,
+                };
+                Repl::new(
+                    api_client,
+                    &base_config,
+                    &mut personalization,
+                    options,
+                    CommandRegistry::builtin(),
+                )
+                .await?
+                .run()
+                .await?;
             }
         }
 
         Ok(())
     }
 
-    /// Handle a single query
-    async fn handle_query(
+    /// Handle the `config` subcommand
+    fn run_config_command(
         &self,
-        api_client: &ApiClient,
-        query: &str,
-        personalization: &mut Personalization,
+        action: Option<&ConfigAction>,
+        create: bool,
+        show: bool,
+        config_path: Option<&Path>,
     ) -> Result<()> {
-        let context = personalization.get_user_context();
-
-        util::print_header("Query");
-        println!("{}", query);
+        // File that set/unset/edit operate on
+        let file_path = config_path
+            .map(Path::to_path_buf)
+            .unwrap_or_else(Config::active_config_path);
 
-        if self.debug {
-            util::print_header("Debug Context");
-            println!("{}", personalization.debug_context());
-        }
-
-        util::print_header("Response");
-        let response = api_client.send_query(query, context).await?;
-        let formatted_response = util::format_response(&response);
-        println!("{}", formatted_response);
+        match action {
+            Some(ConfigAction::Get { key }) => {
+                let loaded_config = Config::load(config_path)?;
+                match loaded_config.get_value(key)? {
+                    Some(toml::Value::String(value)) => println!("{}", value),
+                    Some(value @ toml::Value::Table(_)) => {
+                        print!("{}", toml::to_string_pretty(&value)?)
+                    }
+                    Some(value) => println!("{}", value),
+                    None => return Err(config_err(format!("'{}' is not set", key))),
+                }
+            }
+            Some(ConfigAction::Set { key, value }) => {
+                let mut document = ConfigDocument::open(&file_path)?;
+                document.set(key, value)?;
+                document.save()?;
+                println!("Set {} in {}", key, document.path().display());
+            }
+            Some(ConfigAction::Unset { key }) => {
+                let mut document = ConfigDocument::open(&file_path)?;
+                if document.unset(key)? {
+                    document.save()?;
+                    println!("Removed {} from {}", key, document.path().display());
+                } else {
+                    println!("{} is not set in {}", key, document.path().display());
+                }
+            }
+            Some(ConfigAction::Edit) => {
+                if !file_path.exists() {
+                    Config::default().save_to_file(&file_path)?;
+                }
+                let original = std::fs::read_to_string(&file_path)?;
 
-        // Handle command execution
-        let commands = util::extract_commands(&response);
-        if !commands.is_empty() {
-            println!("\nDetected commands:");
-            for (i, cmd) in commands.iter().enumerate() {
-                print!(
-                    "\nCommand {}: {}\nWould you like to execute this command? (y/N): ",
-                    i + 1,
-                    cmd
-                );
-                std::io::stdout().flush()?;
-
-                let mut input = String::new();
-                std::io::stdin().read_line(&mut input)?;
-
-                if input.trim().eq_ignore_ascii_case("y") {
-                    match util::execute_command(cmd) {
-                        Ok(output) => {
-                            println!("\nCommand Output:");
-                            println!("┌── output ────────────────────────────────────────────────");
-                            for line in output.lines() {
-                                println!("│ {}", line);
-                            }
-                            println!("└{}", "─".repeat(60));
+                loop {
+                    util::open_in_editor(&file_path)?;
+                    let contents = std::fs::read_to_string(&file_path)?;
+                    match crate::config_manager::validate_contents(&contents) {
+                        Ok(()) => {
+                            println!("Configuration saved to {}", file_path.display());
+                            break;
                         }
                         Err(e) => {
-                            eprintln!("Error executing command: {}", e);
+                            eprintln!("{}", e);
+                            if !util::prompt_yes_no("Re-open the editor?", true)? {
+                                std::fs::write(&file_path, original)?;
+                                println!("Changes discarded.");
+                                break;
+                            }
                         }
                     }
-                } else {
-                    println!("Command execution skipped.");
                 }
             }
+            Some(ConfigAction::Path) => {
+                println!("{}", file_path.display());
+            }
+            Some(ConfigAction::Show { origin: false }) => {
+                println!("{}", self.resolve_config(&Config::load(config_path)?)?);
+            }
+            Some(ConfigAction::Show { origin: true }) => {
+                let loaded_config = self.resolve_config(&Config::load(config_path)?)?;
+                for (key, value, origin) in loaded_config.entries()? {
+                    let value = if key.ends_with("api_key") && value.as_str() != Some("") {
+                        "\"[REDACTED]\"".to_string()
+                    } else {
+                        value.to_string()
+                    };
+                    println!("{} = {}  # {}", key, value, origin);
+                }
+            }
+            Some(ConfigAction::Migrate) => {
+                let actions = crate::config_manager::migrate_legacy(
+                    &Config::get_config_path(),
+                    &crate::config_manager::state_dir().join("history"),
+                )?;
+                if actions.is_empty() {
+                    println!("Nothing to migrate.");
+                }
+                for action in actions {
+                    println!("{}", action);
+                }
+            }
+            None if create => {
+                // If no path specified, use default
+                let actual_path = config_path
+                    .map(Path::to_path_buf)
+                    .unwrap_or_else(Config::get_config_path);
+
+                // Create default config
+                let default_config = Config::default();
+                default_config.save_to_file(&actual_path)?;
+
+                println!(
+                    "Created default configuration at: {}",
+                    actual_path.display()
+                );
+            }
+            None if show => {
+                println!("{}", self.resolve_config(&Config::load(config_path)?)?);
+            }
+            None => {
+                // If no option was specified
+                println!(
+                    "Please specify an action (get, set, unset, edit, path, show, migrate) or --create/--show"
+                );
+            }
         }
 
         Ok(())
     }
 
-    /// Run the interactive REPL mode
-    async fn run_interactive_mode(
-        &self,
-        api_client: &ApiClient,
-        personalization: &mut Personalization,
-    ) -> Result<()> {
-        util::print_header("Chris Interactive Mode");
-        println!("Type your queries and press Enter. Use Ctrl+D or type 'exit' to quit.");
-        println!("Type 'help' for available commands.\n");
+    /// Handle the `models` subcommand
+    async fn run_models_command(&self, search: Option<&str>, refresh: bool) -> Result<()> {
+        let mut config = self.resolve_config(&Config::load(self.config.as_deref())?)?;
+        credentials::resolve_api_key(&mut config).await?;
 
-        let mut rl = DefaultEditor::new()?;
-        let history_file = api_client.config().history_file.clone();
+        let catalog = ModelCatalog::load(&config, refresh).await?;
+        let search = search.map(str::to_lowercase);
+        let models: Vec<_> = catalog
+            .models
+            .iter()
+            .filter(|model| {
+                search
+                    .as_deref()
+                    .is_none_or(|search| model.id.to_lowercase().contains(search))
+            })
+            .collect();
 
-        // Try to load history file
-        if api_client.config().store_history {
-            let _ = rl.load_history(&history_file);
+        let width = models.iter().map(|model| model.id.len()).max().unwrap_or(0);
+        for model in &models {
+            let marker = if model.id == config.model() { "*" } else { " " };
+            let context = model
+                .context_length
+                .map_or("-".to_string(), |tokens| tokens.to_string());
+            let pricing = model.pricing.map_or(String::new(), |price| {
+                format!(
+                    "  ${:.2} / ${:.2} per 1M tokens",
+                    price.prompt, price.completion
+                )
+            });
+            println!(
+                "{} {:<width$}  {:>9}{}",
+                marker,
+                model.id,
+                context,
+                pricing,
+                width = width
+            );
         }
 
-        loop {
-            let readline = rl.readline("chris> ");
-            match readline {
-                Ok(line) => {
-                    let line = line.trim();
+        let fetched = chrono::DateTime::from_timestamp(catalog.fetched_at, 0)
+            .map(|time| {
+                time.with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M")
+                    .to_string()
+            })
+            .unwrap_or_default();
+        println!(
+            "\n{} of {} {} models, fetched {}. Context lengths are in tokens.",
+            models.len(),
+            catalog.models.len(),
+            catalog.provider,
+            fetched
+        );
 
-                    // Skip empty lines
-                    if line.is_empty() {
-                        continue;
-                    }
+        Ok(())
+    }
 
-                    // Add to history
-                    if api_client.config().store_history {
-                        let _ = rl.add_history_entry(line);
-                    }
+    /// Handle the `usage` subcommand
+    fn run_usage_command(&self, action: Option<&UsageAction>, month: Option<&str>) -> Result<()> {
+        let config = self.resolve_config(&Config::load(self.config.as_deref())?)?;
+        let file_path = self
+            .config
+            .clone()
+            .unwrap_or_else(Config::active_config_path);
 
-                    // Handle special commands
-                    match line.to_lowercase().as_str() {
-                        "exit" | "quit" => break,
-                        "help" => {
-                            println!("Available commands:");
-                            println!("  help    - Show this help message");
-                            println!("  exit    - Exit the program");
-                            println!("  clear   - Clear the screen");
-                            println!(
-                                "  context - Show user context (if personalization is enabled)"
-                            );
-                            println!("  debug   - Toggle debug mode");
-                            println!("  Any other input will be sent as a query to the model");
-                        }
-                        "clear" => {
-                            print!("\x1B[2J\x1B[1;1H");
-                        }
-                        "context" => {
-                            if let Some(context) = personalization.get_user_context() {
-                                println!("{}", context);
-                            } else {
-                                println!("Personalization is disabled. No context available.");
-                            }
-                        }
-                        "debug" => {
-                            personalization.set_debug(!personalization.is_debug());
-                            println!(
-                                "Debug mode {}",
-                                if personalization.is_debug() {
-                                    "enabled"
-                                } else {
-                                    "disabled"
-                                }
-                            );
+        match action {
+            Some(UsageAction::Budget { soft, hard, clear }) => {
+                if *clear || soft.is_some() || hard.is_some() {
+                    let mut document = ConfigDocument::open(&file_path)?;
+                    if *clear {
+                        document.unset("budget.soft_limit")?;
+                        document.unset("budget.hard_limit")?;
+                    }
+                    for (key, limit) in [("budget.soft_limit", soft), ("budget.hard_limit", hard)] {
+                        if let Some(limit) = limit {
+                            // Debug formatting keeps every digit and always writes a float
+                            document.set(key, &format!("{:?}", limit))?;
                         }
-                        _ => {
-                            // Regular query
-                            if self.debug || personalization.is_debug() {
-                                util::print_header("Debug Context");
-                                println!("{}", personalization.debug_context());
-                            }
+                    }
+                    document.save()?;
+                    println!("Budget saved to {}", file_path.display());
+                }
 
-                            match api_client
-                                .send_query(line, personalization.get_user_context())
-                                .await
-                            {
-                                Ok(response) => {
-                                    util::print_header("Response");
-                                    println!("{}", util::format_response(&response));
-                                }
-                                Err(e) => {
-                                    eprintln!("Error: {}", e);
-                                }
-                            }
+                // Reload so the limits shown include the change just made
+                let budget = Config::load(self.config.as_deref())?.budget;
+                let describe = |limit: Option<f64>| {
+                    limit.map_or("not set".to_string(), |limit| format!("${:.2}", limit))
+                };
+                println!("Soft limit: {}", describe(budget.soft_limit));
+                println!("Hard limit: {}", describe(budget.hard_limit));
+            }
+            None => {
+                let month = month.map_or_else(current_month, str::to_string);
+                let ledger = UsageLedger::open();
+                let summary = Summary::from_records(&ledger.month(&month)?);
+
+                util::print_header(&format!("Usage for {}", month));
+                print_totals("Total", &summary.total);
+                if let Some(limit) = config.budget.hard_limit.or(config.budget.soft_limit) {
+                    println!(
+                        "Budget: ${:.2} of ${:.2}{}",
+                        summary.total.cost,
+                        limit,
+                        match (config.budget.soft_limit, config.budget.hard_limit) {
+                            (Some(soft), Some(_)) => format!(" (soft limit ${:.2})", soft),
+                            (Some(_), None) => " (soft limit)".to_string(),
+                            _ => String::new(),
                         }
+                    );
+                }
+
+                for (title, totals) in [
+                    ("By model", &summary.by_model),
+                    ("By profile", &summary.by_profile),
+                    ("By day", &summary.by_day),
+                ] {
+                    if totals.is_empty() {
+                        continue;
+                    }
+                    println!("\n{}:", title);
+                    for (name, totals) in totals {
+                        print_totals(&format!("  {}", name), totals);
                     }
                 }
-                Err(ReadlineError::Interrupted) => {
-                    println!("Ctrl+C pressed. Press Ctrl+D or type 'exit' to quit.");
+
+                if summary.total.unpriced > 0 {
+                    println!(
+                        "\n{} requests used models without a price. Add them to [pricing] in {}",
+                        summary.total.unpriced,
+                        file_path.display()
+                    );
                 }
-                Err(ReadlineError::Eof) => {
-                    println!("Exiting...");
-                    break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Handle the `auth` subcommand
+    async fn run_auth_command(&self, action: &AuthAction) -> Result<()> {
+        let config = self.resolve_config(&Config::load(self.config.as_deref())?)?;
+        let file_path = self
+            .config
+            .clone()
+            .unwrap_or_else(Config::active_config_path);
+
+        match action {
+            AuthAction::Login { provider, backend } => {
+                let provider = provider.clone().unwrap_or_else(|| config.provider.clone());
+                let mut credentials = config.credentials.clone();
+                credentials.backend = backend.unwrap_or(credentials.backend);
+
+                let store = credentials::create_store(&credentials)?.ok_or_else(|| {
+                    credential_err(
+                        "No credential backend configured. \
+                        Use `chris auth login --backend secret-service` or `--backend encrypted-file`.",
+                    )
+                })?;
+
+                let key = read_api_key(&provider)?;
+                store.set(&provider, &key).await?;
+                println!("Stored {} API key in the {}.", provider, store.name());
+
+                // Remember the backend and drop any plaintext copy of the key
+                let mut document = ConfigDocument::open(&file_path)?;
+                let mut changed = false;
+                if credentials.backend != config.credentials.backend {
+                    document.set("credentials.backend", credentials.backend.as_str())?;
+                    changed = true;
+                }
+                if document
+                    .get_str(provider.api_key_field())
+                    .is_some_and(|key| !key.is_empty())
+                {
+                    document.unset(provider.api_key_field())?;
+                    println!(
+                        "Removed plaintext {} from {}",
+                        provider.api_key_field(),
+                        file_path.display()
+                    );
+                    changed = true;
+                }
+                if changed {
+                    document.save()?;
+                }
+            }
+            AuthAction::Logout { provider } => {
+                let provider = provider.clone().unwrap_or_else(|| config.provider.clone());
+                let store = credentials::create_store(&config.credentials)?.ok_or_else(|| {
+                    credential_err("No credential backend configured; nothing to remove.")
+                })?;
+
+                if store.delete(&provider).await? {
+                    println!("Removed {} API key from the {}.", provider, store.name());
+                } else {
+                    println!("No {} API key stored in the {}.", provider, store.name());
+                }
+            }
+            AuthAction::Status => {
+                let document = ConfigDocument::open(&file_path)?;
+                let store = credentials::create_store(&config.credentials)?;
+                let mut plaintext = false;
+
+                println!(
+                    "Credential backend: {}",
+                    config.credentials.backend.as_str()
+                );
+                for provider in Provider::ALL {
+                    let source = if let ConfigSource::Environment(var) =
+                        config.origin(provider.api_key_field())
+                    {
+                        format!("environment (${})", var)
+                    } else if document
+                        .get_str(provider.api_key_field())
+                        .is_some_and(|key| !key.is_empty())
+                    {
+                        plaintext = true;
+                        format!("config file, in plaintext ({})", file_path.display())
+                    } else if let Some(store) = &store {
+                        match store.get(&provider).await {
+                            Ok(Some(_)) => store.name().to_string(),
+                            Ok(None) => "not set".to_string(),
+                            Err(e) => format!("unavailable ({})", e),
+                        }
+                    } else {
+                        "not set".to_string()
+                    };
+
+                    println!("  {:<11} {}", provider.to_string(), source);
                 }
-                Err(err) => {
-                    eprintln!("Error: {}", err);
-                    break;
+
+                if plaintext {
+                    println!(
+                        "\nRun `chris auth login` to move plaintext keys into a credential backend."
+                    );
                 }
             }
         }
 
-        // Save history on exit
-        if api_client.config().store_history {
-            if let Err(e) = rl.save_history(&history_file) {
-                log::warn!("Failed to save history: {}", e);
+        Ok(())
+    }
+
+    /// Apply the selected profile and command line overrides to the loaded configuration
+    fn resolve_config(&self, base_config: &Config) -> Result<Config> {
+        let mut config = base_config.with_profile(self.profile.as_deref())?;
+
+        // Override config with command line arguments
+        if let Some(model) = &self.model {
+            config.apply_override(
+                &format!("{}_model", config.provider.as_str()),
+                toml::Value::String(model.clone()),
+                ConfigSource::CommandLine("--model".to_string()),
+            )?;
+        }
+        if let Some(system) = &self.system {
+            config.apply_override(
+                "system_prompt",
+                toml::Value::String(system.clone()),
+                ConfigSource::CommandLine("--system".to_string()),
+            )?;
+        }
+
+        // Generation parameters
+        let stop = (!self.stop.is_empty()).then(|| {
+            toml::Value::Array(self.stop.iter().cloned().map(toml::Value::String).collect())
+        });
+        let parameters = [
+            ("temperature", self.temperature.map(toml::Value::Float)),
+            ("top_p", self.top_p.map(toml::Value::Float)),
+            ("stop", stop),
+            ("seed", self.seed.map(toml::Value::Integer)),
+            (
+                "presence_penalty",
+                self.presence_penalty.map(toml::Value::Float),
+            ),
+            (
+                "frequency_penalty",
+                self.frequency_penalty.map(toml::Value::Float),
+            ),
+        ];
+        for (key, value) in parameters {
+            if let Some(value) = value {
+                config.apply_override(
+                    key,
+                    value,
+                    ConfigSource::CommandLine(format!("--{}", key.replace('_', "-"))),
+                )?;
             }
         }
 
+        Ok(config)
+    }
+
+    /// Handle a single query
+    async fn handle_query(
+        &self,
+        api_client: &ApiClient,
+        query: &str,
+        personalization: &mut Personalization,
+    ) -> Result<()> {
+        util::print_header("Query");
+        println!("{}", query);
+
+        if self.debug {
+            print_debug_context(api_client, personalization)?;
+        }
+
+        let completion = send_tracked(api_client, &[], query, personalization, self.debug).await?;
+        let response = completion.content.as_str();
+        util::print_header("Response");
+        let formatted_response = util::format_response(response);
+        println!("{}", formatted_response);
+        if self.verbose {
+            print_usage_footer(api_client, &completion);
+        }
+
+        if let Some(dir) = &self.save_code {
+            code_blocks::save_all(response, dir)?;
+        }
+
+        if let Some(mut menu) = CommandMenu::new(response) {
+            menu.run(api_client, personalization).await?;
+        }
+
         Ok(())
     }
 }
+
+/// Look up the API key for the selected provider and create the API client
+pub(crate) async fn build_api_client(mut config: Config) -> Result<ApiClient> {
+    credentials::resolve_api_key(&mut config).await?;
+    create_api_client(config)
+}
+
+/// Check a model named on the command line against the provider's list of models
+///
+/// The check is skipped with a warning when the list can't be fetched, so that an
+/// unreachable models endpoint doesn't block queries.
+pub(crate) async fn check_model(config: &Config, model: &str) -> Result<()> {
+    let catalog = match ModelCatalog::load(config, false).await {
+        Ok(catalog) => catalog,
+        Err(e) => {
+            log::warn!("Could not check that model '{}' exists: {}", model, e);
+            return Ok(());
+        }
+    };
+    if catalog.get(model).is_some() {
+        return Ok(());
+    }
+
+    // The cached list may predate the model
+    ModelCatalog::load(config, true)
+        .await
+        .unwrap_or(catalog)
+        .check(model)
+}
+
+/// Handle the `templates` subcommand
+fn run_templates_command(action: Option<&TemplateAction>) -> Result<()> {
+    let library = PromptLibrary::load()?;
+
+    match action {
+        None | Some(TemplateAction::List) => {
+            let width = library.names().iter().map(|name| name.len()).max();
+            for template in library.templates() {
+                println!(
+                    "{:<width$}  {}",
+                    template.name,
+                    template.description,
+                    width = width.unwrap_or(0)
+                );
+            }
+            println!(
+                "\nAdd your own templates to {}",
+                prompts::prompts_dir().display()
+            );
+        }
+        Some(TemplateAction::Show { name }) => {
+            let template = library.get(name)?;
+            println!("Name: {}", template.name);
+            println!("Description: {}", template.description);
+            println!("Source: {}", template.source);
+            println!("Arguments: {}", template.arguments().join(", "));
+            util::print_header("Prompt");
+            println!("{}", template.prompt);
+        }
+    }
+
+    Ok(())
+}
+
+/// Render a template from the library
+///
+/// A missing `input` argument is read from stdin when stdin is not a terminal, so
+/// that output can be piped in, e.g. `git diff | chris run review-diff`.
+fn render_template(name: &str, args: &[(String, String)]) -> Result<String> {
+    let library = PromptLibrary::load()?;
+    let template = library.get(name)?;
+    let mut args: BTreeMap<String, String> = args.iter().cloned().collect();
+
+    if template.arguments().contains(&prompts::INPUT_ARG)
+        && !args.contains_key(prompts::INPUT_ARG)
+        && !std::io::stdin().is_terminal()
+    {
+        let mut input = String::new();
+        std::io::stdin().read_to_string(&mut input)?;
+        args.insert(prompts::INPUT_ARG.to_string(), input);
+    }
+
+    template.render(&args)
+}
+
+/// Print one line of a usage report
+fn print_totals(label: &str, totals: &Totals) {
+    println!(
+        "{}: {} requests, {} prompt + {} completion tokens, ${:.4}",
+        label, totals.requests, totals.prompt_tokens, totals.completion_tokens, totals.cost
+    );
+}
+
+/// Send a query following `history`, trimmed to fit the context window
+///
+/// In debug mode, reports how much of the context window the request uses and which
+/// earlier turns had to be left out.
+pub(crate) async fn send_tracked(
+    api_client: &ApiClient,
+    history: &[Message],
+    query: &str,
+    personalization: &Personalization,
+    debug: bool,
+) -> Result<Completion> {
+    let prepared =
+        api_client.prepare_messages(history, query, personalization.get_user_context())?;
+    if debug {
+        util::print_header("Context Window");
+        println!(
+            "{} of {} tokens used by the prompt, {} reserved for the response",
+            prepared.prompt_tokens,
+            prepared.context_window,
+            api_client.config().max_tokens
+        );
+        if prepared.dropped_turns > 0 {
+            println!(
+                "Dropped the {} oldest turn(s) ({} tokens) to fit the context window",
+                prepared.dropped_turns, prepared.dropped_tokens
+            );
+        }
+    } else if prepared.dropped_turns > 0 {
+        log::info!(
+            "Dropped {} turn(s) ({} tokens) to fit the context window",
+            prepared.dropped_turns,
+            prepared.dropped_tokens
+        );
+    }
+
+    send_recorded(api_client, prepared.messages).await
+}
+
+/// Send messages, enforcing the monthly budget and recording the usage in the ledger
+pub(crate) async fn send_recorded(
+    api_client: &ApiClient,
+    messages: Vec<Message>,
+) -> Result<Completion> {
+    let ledger = UsageLedger::open();
+    if let Some(warning) = ledger.check_budget(&api_client.config().budget)? {
+        eprintln!("{}", warning);
+    }
+
+    let completion = api_client.send_messages(messages).await?;
+
+    // Failing to record usage should not lose the response
+    if let Some(record) = UsageRecord::new(api_client.config(), &completion) {
+        if let Err(e) = ledger.record(&record) {
+            log::warn!("Failed to record usage: {}", e);
+        }
+    }
+
+    Ok(completion)
+}
+
+/// Print the token usage and cost of a completion
+pub(crate) fn print_usage_footer(api_client: &ApiClient, completion: &Completion) {
+    match UsageRecord::new(api_client.config(), completion) {
+        Some(record) => println!(
+            "\n[{}] {} prompt + {} completion tokens, {}",
+            record.model,
+            record.prompt_tokens,
+            record.completion_tokens,
+            record
+                .cost
+                .map_or("no price configured".to_string(), |cost| format!(
+                    "${:.4}",
+                    cost
+                ))
+        ),
+        None => println!("\n[{}] token usage not reported", completion.model),
+    }
+}
+
+/// Print the personalization context and the rendered system prompt
+pub(crate) fn print_debug_context(
+    api_client: &ApiClient,
+    personalization: &Personalization,
+) -> Result<()> {
+    util::print_header("Debug Context");
+    println!("{}", personalization.debug_context());
+
+    util::print_header("System Prompt");
+    println!(
+        "{}",
+        api_client.system_prompt(personalization.get_user_context())?
+    );
+
+    Ok(())
+}
+
+/// Read an API key without echoing it, or from stdin when it is not a terminal
+fn read_api_key(provider: &Provider) -> Result<String> {
+    let key = if std::io::stdin().is_terminal() {
+        rpassword::prompt_password(format!("{} API key: ", provider))?
+    } else {
+        let mut key = String::new();
+        std::io::stdin().read_line(&mut key)?;
+        key
+    };
+
+    credentials::normalize_key(&key)
+}
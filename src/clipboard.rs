@@ -0,0 +1,56 @@
+//! Copying text to the clipboard.
+//!
+//! Text is sent to the terminal with the OSC 52 escape sequence, which most terminal
+//! emulators support and which also works over SSH and inside tmux. When standard
+//! output is not a terminal, or the text is too long for the sequence, it is written
+//! to a new file in the temporary directory instead.
+
+use crate::error::Result;
+use base64::Engine;
+use std::io::{IsTerminal, Write};
+use std::path::PathBuf;
+
+/// Longest encoded text sent with OSC 52; many terminals ignore longer sequences
+const OSC52_LIMIT: usize = 100_000;
+
+/// Where copied text ended up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Copied {
+    /// Sent to the terminal's clipboard
+    Clipboard,
+    /// Written to a file
+    File(PathBuf),
+}
+
+/// Copy `text` to the clipboard, or to a file when the clipboard can't be reached
+pub fn copy(text: &str) -> Result<Copied> {
+    let encoded = base64::engine::general_purpose::STANDARD.encode(text);
+    let mut stdout = std::io::stdout();
+    if stdout.is_terminal() && encoded.len() <= OSC52_LIMIT {
+        stdout.write_all(osc52(&encoded, std::env::var_os("TMUX").is_some()).as_bytes())?;
+        stdout.flush()?;
+        return Ok(Copied::Clipboard);
+    }
+
+    // A new file each time, readable only by the user, so that nothing else on a
+    // shared temporary directory can read it or redirect the write
+    let mut file = tempfile::Builder::new()
+        .prefix("chris-copy-")
+        .suffix(".txt")
+        .tempfile()?;
+    file.write_all(text.as_bytes())?;
+    let (_, path) = file.keep().map_err(|e| e.error)?;
+    Ok(Copied::File(path))
+}
+
+/// The escape sequence setting the clipboard to base64 `encoded` text
+///
+/// Inside tmux the sequence is wrapped so that tmux passes it on to the terminal.
+pub fn osc52(encoded: &str, tmux: bool) -> String {
+    let sequence = format!("\x1b]52;c;{}\x07", encoded);
+    if tmux {
+        format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b"))
+    } else {
+        sequence
+    }
+}
@@ -0,0 +1,451 @@
+//! Code blocks in responses.
+//!
+//! [`parse`] splits a Markdown response into text and fenced code blocks. Fences may
+//! use three or more backticks or tildes and may be indented by up to three columns,
+//! or more inside list items; a line indented further is indented code. Tabs stop
+//! every four columns. A block ends at a fence of the same character that is at least
+//! as long, and a block left open runs to the end of the response. An opening fence
+//! with a language inside a block starts a nested block, so Markdown examples keep
+//! their inner fences.
+//!
+//! Blocks are numbered from 1 in the order they appear, matching the numbers shown by
+//! [`format_response`](crate::util::format_response). They can be saved to files
+//! named after their language; an existing file is only replaced after showing a diff
+//! and asking.
+
+mod commands;
+mod risk;
+
+pub use commands::ShellCommand;
+pub use risk::Risk;
+
+use crate::error::Result;
+use crate::util;
+use similar::TextDiff;
+use std::path::{Path, PathBuf};
+
+/// Fence languages and the file extensions used for them
+const EXTENSIONS: &[(&[&str], &str)] = &[
+    (&["bash", "sh", "shell", "zsh", "console"], "sh"),
+    (&["fish"], "fish"),
+    (&["powershell", "ps1", "pwsh"], "ps1"),
+    (&["bat", "batch", "cmd"], "bat"),
+    (&["python", "py", "python3"], "py"),
+    (&["rust", "rs"], "rs"),
+    (&["javascript", "js", "node"], "js"),
+    (&["typescript", "ts"], "ts"),
+    (&["jsx"], "jsx"),
+    (&["tsx"], "tsx"),
+    (&["go", "golang"], "go"),
+    (&["c", "h"], "c"),
+    (&["cpp", "c++", "cxx", "hpp"], "cpp"),
+    (&["csharp", "cs", "c#"], "cs"),
+    (&["java"], "java"),
+    (&["kotlin", "kt"], "kt"),
+    (&["swift"], "swift"),
+    (&["ruby", "rb"], "rb"),
+    (&["php"], "php"),
+    (&["perl", "pl"], "pl"),
+    (&["lua"], "lua"),
+    (&["sql", "postgresql", "mysql", "sqlite"], "sql"),
+    (&["html"], "html"),
+    (&["css"], "css"),
+    (&["scss"], "scss"),
+    (&["json"], "json"),
+    (&["yaml", "yml"], "yaml"),
+    (&["toml"], "toml"),
+    (&["ini", "cfg"], "ini"),
+    (&["xml"], "xml"),
+    (&["markdown", "md"], "md"),
+    (&["hcl", "terraform", "tf"], "tf"),
+    (&["nginx", "conf", "apache"], "conf"),
+    (&["diff", "patch"], "diff"),
+    (&["csv"], "csv"),
+];
+
+/// Fence languages of shell scripts
+///
+/// fish isn't listed: its syntax isn't understood by `sh`, so its commands aren't run.
+const SHELL_LANGUAGES: &[&str] = &[
+    "sh",
+    "bash",
+    "shell",
+    "zsh",
+    "ksh",
+    "dash",
+    "shellscript",
+    "shell-script",
+];
+
+/// Shells whose blocks are run by that shell rather than by `sh`, since they may use
+/// syntax `sh` doesn't have
+const NAMED_SHELLS: &[&str] = &["bash", "zsh", "ksh"];
+
+/// Fence languages of terminal sessions, with prompts before commands
+const CONSOLE_LANGUAGES: &[&str] = &[
+    "console",
+    "terminal",
+    "shell-session",
+    "sh-session",
+    "shellsession",
+];
+
+/// Fence languages of PowerShell scripts
+const POWERSHELL_LANGUAGES: &[&str] = &["powershell", "pwsh", "ps1", "ps"];
+
+/// Languages whose files are conventionally named without an extension
+const FILE_NAMES: &[(&str, &str)] = &[
+    ("dockerfile", "Dockerfile"),
+    ("makefile", "Makefile"),
+    ("make", "Makefile"),
+];
+
+/// How a code block can be run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    /// A script for a POSIX-like shell
+    Shell,
+    /// A terminal session: commands after a `$ ` prompt, mixed with their output
+    Console,
+    /// A PowerShell script
+    PowerShell,
+    /// Anything that isn't run as a command
+    Other,
+}
+
+/// A part of a Markdown response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    /// Lines outside code blocks, each ending with a newline
+    Text(String),
+    /// A fenced code block
+    Code(CodeBlock),
+}
+
+/// A fenced code block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeBlock {
+    /// Language named after the opening fence, if any
+    pub lang: String,
+
+    /// Lines inside the fences, each ending with a newline
+    pub content: String,
+}
+
+impl CodeBlock {
+    /// The fence language in lower case, without any attributes after it
+    fn lang_name(&self) -> String {
+        self.lang
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_lowercase()
+    }
+
+    /// File extension for the block's language, or `txt` when it isn't known
+    pub fn extension(&self) -> &'static str {
+        let lang = self.lang_name();
+        EXTENSIONS
+            .iter()
+            .find(|(names, _)| names.contains(&lang.as_str()))
+            .map_or("txt", |(_, extension)| extension)
+    }
+
+    /// Default file name for the block numbered `n`
+    pub fn file_name(&self, n: usize) -> String {
+        let lang = self.lang_name();
+        match FILE_NAMES.iter().find(|(name, _)| *name == lang) {
+            Some((_, file_name)) => file_name.to_string(),
+            None => format!("code-{}.{}", n, self.extension()),
+        }
+    }
+
+    /// The shell named by the fence language, if commands have to be run by it
+    pub fn shell(&self) -> Option<&'static str> {
+        let lang = self.lang_name();
+        NAMED_SHELLS.iter().copied().find(|shell| *shell == lang)
+    }
+
+    /// Classify the block by its fence language and, when that doesn't settle it,
+    /// by whether it starts with a prompt
+    pub fn language(&self) -> Language {
+        let lang = self.lang_name();
+        let percent = commands::percent_prompts(&self.content);
+        let prompted = self
+            .content
+            .lines()
+            .find(|line| !line.trim().is_empty())
+            .is_some_and(|line| commands::strip_prompt(line, percent).is_some());
+
+        if CONSOLE_LANGUAGES.contains(&lang.as_str()) {
+            Language::Console
+        } else if POWERSHELL_LANGUAGES.contains(&lang.as_str()) {
+            Language::PowerShell
+        } else if (lang.is_empty() || SHELL_LANGUAGES.contains(&lang.as_str())) && prompted {
+            Language::Console
+        } else if SHELL_LANGUAGES.contains(&lang.as_str()) {
+            Language::Shell
+        } else {
+            Language::Other
+        }
+    }
+
+    /// The commands in the block, in order
+    ///
+    /// Blocks that aren't shell, terminal session or PowerShell code have none.
+    pub fn commands(&self) -> Vec<ShellCommand> {
+        commands::split(self)
+    }
+}
+
+/// An opening or closing code fence
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Fence {
+    /// Columns of whitespace before the fence
+    indent: usize,
+    /// Backtick or tilde
+    marker: char,
+    /// Number of marker characters
+    len: usize,
+    /// Text after the fence, naming the language on an opening fence
+    info: String,
+}
+
+impl Fence {
+    /// Read a fence from a line
+    fn parse(line: &str) -> Option<Self> {
+        let trimmed = line.trim_start_matches([' ', '\t']);
+        let marker = trimmed.chars().next().filter(|c| *c == '`' || *c == '~')?;
+        let len = trimmed.chars().take_while(|c| *c == marker).count();
+        let info = trimmed[len..].trim();
+        // A backtick fence can't have backticks after it; that's inline code
+        if len < 3 || (marker == '`' && info.contains('`')) {
+            return None;
+        }
+
+        Some(Self {
+            indent: indent(line),
+            marker,
+            len,
+            info: info.to_string(),
+        })
+    }
+
+    /// Whether `other` ends a block opened by this fence
+    fn is_closed_by(&self, other: &Fence) -> bool {
+        other.marker == self.marker && other.len >= self.len && other.info.is_empty()
+    }
+
+    /// Whether `other` opens a block nested in one opened by this fence
+    fn is_nested(&self, other: &Fence) -> bool {
+        other.marker == self.marker && other.len >= self.len && !other.info.is_empty()
+    }
+
+    /// Remove the fence's indentation from a line inside the block
+    fn dedent<'a>(&self, line: &'a str) -> &'a str {
+        let mut column = 0;
+        for (i, c) in line.char_indices() {
+            if column >= self.indent || !matches!(c, ' ' | '\t') {
+                return &line[i..];
+            }
+            column = next_column(column, c);
+        }
+        ""
+    }
+}
+
+/// Split a Markdown response into text and code blocks
+pub fn parse(markdown: &str) -> Vec<Segment> {
+    split(markdown).0
+}
+
+/// Whether `markdown` ends inside a code block that hasn't been closed
+pub fn ends_in_open_block(markdown: &str) -> bool {
+    split(markdown).1
+}
+
+/// Split Markdown into segments, and tell whether the last code block was left open
+fn split(markdown: &str) -> (Vec<Segment>, bool) {
+    let mut segments = Vec::new();
+    let mut text = String::new();
+    // The opening fence, the block so far, and how many nested blocks are open
+    let mut open: Option<(Fence, CodeBlock, usize)> = None;
+    // Column where the content of the list item being read starts
+    let mut list: Option<usize> = None;
+
+    for line in markdown.lines() {
+        // Four columns past the container's content make a line indented code
+        let fence = Fence::parse(line).filter(|fence| fence.indent < list.unwrap_or(0) + 4);
+        if open.is_none() && fence.is_none() {
+            if let Some(content) = list_item(line) {
+                list = Some(content);
+            } else if !line.trim().is_empty() && indent(line) < list.unwrap_or(0) {
+                list = None;
+            }
+        }
+
+        match (&mut open, fence) {
+            (None, Some(fence)) => {
+                if !text.is_empty() {
+                    segments.push(Segment::Text(std::mem::take(&mut text)));
+                }
+                let block = CodeBlock {
+                    lang: fence.info.clone(),
+                    content: String::new(),
+                };
+                open = Some((fence, block, 0));
+            }
+            (None, None) => {
+                text.push_str(line);
+                text.push('\n');
+            }
+            (Some((opening, block, depth)), fence) => {
+                match fence {
+                    Some(fence) if opening.is_closed_by(&fence) && *depth == 0 => {
+                        if let Some((_, block, _)) = open.take() {
+                            segments.push(Segment::Code(block));
+                        }
+                        continue;
+                    }
+                    Some(fence) if opening.is_closed_by(&fence) => *depth -= 1,
+                    Some(fence) if opening.is_nested(&fence) => *depth += 1,
+                    _ => {}
+                }
+                block.content.push_str(opening.dedent(line));
+                block.content.push('\n');
+            }
+        }
+    }
+
+    let unterminated = open.is_some();
+    if let Some((_, block, _)) = open {
+        segments.push(Segment::Code(block));
+    }
+    if !text.is_empty() {
+        segments.push(Segment::Text(text));
+    }
+    (segments, unterminated)
+}
+
+/// Columns of whitespace at the start of a line
+fn indent(line: &str) -> usize {
+    line.chars()
+        .take_while(|c| matches!(c, ' ' | '\t'))
+        .fold(0, next_column)
+}
+
+/// Column after `c`, with tabs stopping every four columns
+fn next_column(column: usize, c: char) -> usize {
+    match c {
+        '\t' => column + 4 - column % 4,
+        _ => column + 1,
+    }
+}
+
+/// Column where the content of a list item starts, if the line begins one
+fn list_item(line: &str) -> Option<usize> {
+    let rest = line.trim_start_matches([' ', '\t']);
+    let digits = rest.chars().take_while(char::is_ascii_digit).count();
+    let marker = match rest[digits..].chars().next()? {
+        '-' | '*' | '+' if digits == 0 => 1,
+        '.' | ')' if (1..=9).contains(&digits) => digits + 1,
+        _ => return None,
+    };
+    rest[marker..]
+        .starts_with([' ', '\t'])
+        .then_some(indent(line) + marker + 1)
+}
+
+/// Extract every code block in a response
+pub fn extract(response: &str) -> Vec<CodeBlock> {
+    parse(response)
+        .into_iter()
+        .filter_map(|segment| match segment {
+            Segment::Code(block) => Some(block),
+            Segment::Text(_) => None,
+        })
+        .collect()
+}
+
+/// Default file names for the blocks of one response, in order
+///
+/// Only the first block of a language with a conventional name gets that name; later
+/// ones have their number added (`Dockerfile.3`) so that they don't replace it.
+pub fn file_names(blocks: &[CodeBlock]) -> Vec<String> {
+    let mut names: Vec<String> = Vec::with_capacity(blocks.len());
+    for (i, block) in blocks.iter().enumerate() {
+        let name = block.file_name(i + 1);
+        if names.contains(&name) {
+            names.push(format!("{}.{}", name, i + 1));
+        } else {
+            names.push(name);
+        }
+    }
+    names
+}
+
+/// Where to save a block with the default file name `name` when the user gave `path`
+///
+/// A directory, or a path ending with a separator, gets the default name.
+pub fn target_path(name: &str, path: &Path) -> PathBuf {
+    let is_dir = path.is_dir() || path.as_os_str().to_string_lossy().ends_with(['/', '\\']);
+    if is_dir {
+        path.join(name)
+    } else {
+        path.to_path_buf()
+    }
+}
+
+/// Write a block to `path`
+///
+/// When a different file is already there, a diff is shown and the user is asked
+/// before it is replaced. Returns whether the file was written.
+pub fn save(block: &CodeBlock, path: &Path) -> Result<bool> {
+    if let Ok(existing) = std::fs::read_to_string(path) {
+        if existing == block.content {
+            println!("{} is already up to date.", path.display());
+            return Ok(false);
+        }
+
+        let current = path.display().to_string();
+        let diff = TextDiff::from_lines(&existing, &block.content);
+        print!(
+            "{}",
+            diff.unified_diff()
+                .header(&current, &format!("{} (new)", current))
+        );
+        if !util::prompt_yes_no(&format!("Overwrite {}?", current), false)? {
+            println!("Kept {}", current);
+            return Ok(false);
+        }
+    } else if path.exists() {
+        return Err(crate::error::validation_err(format!(
+            "{} exists and is not a text file",
+            path.display()
+        )));
+    }
+
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, &block.content)?;
+    Ok(true)
+}
+
+/// Save every block in a response to `dir` under its default name
+pub fn save_all(response: &str, dir: &Path) -> Result<()> {
+    let blocks = extract(response);
+    if blocks.is_empty() {
+        println!("No code blocks to save.");
+    }
+    for (i, (block, name)) in blocks.iter().zip(file_names(&blocks)).enumerate() {
+        let path = dir.join(name);
+        if save(block, &path)? {
+            println!("Saved code block {} to {}", i + 1, path.display());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,385 @@
+//! Splitting code blocks into the commands they hold.
+//!
+//! A command usually takes one line, but continues onto the next when the line ends
+//! with a backslash (a backtick in PowerShell) or a pipe or `&&`/`||`, when a quote,
+//! `if`/`for`/`while`/`case` or brace is still open, or while a heredoc is being read.
+//! Full-line comments are kept apart as the description of the command after them.
+//! In a terminal session only lines after a prompt are commands; the lines after each
+//! command are its example output.
+
+use super::{CodeBlock, Language, Risk};
+
+/// Prompts that start a command in a terminal session
+const PROMPTS: &[&str] = &["$ ", "❯ "];
+
+/// zsh's prompt, which is only taken as one in blocks that don't use `$ `
+const PERCENT_PROMPT: &str = "% ";
+
+/// Prompts on the continuation lines of a command in a terminal session
+const CONTINUATION_PROMPTS: &[&str] = &[">> ", "> "];
+
+/// Shell keywords that open a compound command
+const OPENERS: &[&str] = &["if", "for", "while", "until", "case", "select", "{"];
+
+/// Shell keywords that close a compound command
+const CLOSERS: &[&str] = &["fi", "done", "esac", "}"];
+
+/// Shell words after which another command starts
+const SEPARATORS: &[&str] = &["then", "do", "else", "elif", "{", "!", "time"];
+
+/// A command found in a code block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShellCommand {
+    /// How the command is run
+    pub language: Language,
+
+    /// Shell that has to run the command, when the block names one
+    pub shell: Option<&'static str>,
+
+    /// The command, without prompts, comments or output
+    pub text: String,
+
+    /// Comment lines just before the command, without their `#`
+    pub comment: Option<String>,
+
+    /// Example output shown after the command in a terminal session
+    pub output: String,
+}
+
+impl ShellCommand {
+    /// How careful to be before running the command
+    pub fn risk(&self) -> Risk {
+        Risk::of(&self.text)
+    }
+}
+
+/// Whether `% ` can be a prompt in a block with this content
+///
+/// Output often has `%` at the start of a line, like curl's progress header, so it is
+/// only read as a prompt in blocks that have no `$ ` prompts.
+pub(super) fn percent_prompts(content: &str) -> bool {
+    !content.lines().any(|line| line.starts_with("$ "))
+}
+
+/// The command after a prompt, if the line starts with one
+///
+/// Prompts must be at the start of the line, since indented lines are output. Besides
+/// the bare prompts this accepts `user@host:~/dir$ ` and PowerShell's `PS C:\> `, and
+/// `% ` when `percent` is set.
+pub(super) fn strip_prompt(line: &str, percent: bool) -> Option<&str> {
+    if let Some(command) = PROMPTS.iter().find_map(|prompt| line.strip_prefix(prompt)) {
+        return Some(command);
+    }
+    if let Some(command) = line.strip_prefix(PERCENT_PROMPT).filter(|_| percent) {
+        return Some(command);
+    }
+
+    let (first, rest) = line.split_once(' ')?;
+    let host_prompt = (first.contains('@') || first.contains(':'))
+        && (first.ends_with('$') || first.ends_with('#') || first.ends_with('%'));
+    let powershell_prompt = first == "PS>" || (first == "PS" && rest.contains("> "));
+    if host_prompt {
+        Some(rest)
+    } else if powershell_prompt {
+        rest.split_once("> ")
+            .map(|(_, command)| command)
+            .or(Some(rest))
+    } else {
+        None
+    }
+}
+
+/// Split a block into its commands
+pub(super) fn split(block: &CodeBlock) -> Vec<ShellCommand> {
+    let language = block.language();
+    if language == Language::Other {
+        return Vec::new();
+    }
+    let percent = percent_prompts(&block.content);
+    // A session is only read as one if it has prompts; otherwise every line is code
+    let session = block
+        .content
+        .lines()
+        .any(|line| strip_prompt(line, percent).is_some())
+        && (language == Language::Console
+            || block
+                .content
+                .lines()
+                .find(|line| !line.trim().is_empty())
+                .is_some_and(|line| strip_prompt(line, percent).is_some()));
+
+    let mut commands: Vec<ShellCommand> = Vec::new();
+    let mut comment: Vec<&str> = Vec::new();
+    let mut lines = block.content.lines();
+
+    while let Some(line) = lines.next() {
+        let line = if session {
+            match strip_prompt(line, percent) {
+                Some(command) => command,
+                None => {
+                    if let Some(command) = commands.last_mut() {
+                        command.output.push_str(line);
+                        command.output.push('\n');
+                    }
+                    continue;
+                }
+            }
+        } else {
+            line
+        };
+
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            comment.clear();
+            continue;
+        }
+        if let Some(text) = trimmed.strip_prefix('#') {
+            // A shebang isn't a description
+            if !text.starts_with('!') {
+                comment.push(text.trim());
+            }
+            continue;
+        }
+
+        let mut reader = Reader::new(language);
+        reader.feed(line);
+        let mut text = line.trim_end().to_string();
+        while reader.needs_more() {
+            let Some(next) = lines.next() else {
+                break;
+            };
+            let next = if session {
+                CONTINUATION_PROMPTS
+                    .iter()
+                    .find_map(|prompt| next.strip_prefix(prompt))
+                    .unwrap_or(next)
+            } else {
+                next
+            };
+            reader.feed(next);
+            text.push('\n');
+            text.push_str(next.trim_end());
+        }
+
+        commands.push(ShellCommand {
+            language: match language {
+                Language::PowerShell => Language::PowerShell,
+                _ if session => Language::Console,
+                _ => Language::Shell,
+            },
+            shell: block.shell(),
+            text: text.trim().to_string(),
+            comment: (!comment.is_empty()).then(|| comment.join("\n")),
+            output: String::new(),
+        });
+        comment.clear();
+    }
+
+    commands
+}
+
+/// The line that ends a heredoc
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Terminator {
+    /// A line holding just the delimiter
+    Line(String),
+    /// The delimiter after any tabs, for `<<-`
+    Indented(String),
+    /// A line starting with the end of a PowerShell here-string
+    Prefix(&'static str),
+}
+
+impl Terminator {
+    /// Whether `line` ends the heredoc
+    fn matches(&self, line: &str) -> bool {
+        match self {
+            Terminator::Line(delimiter) => line.trim_end() == delimiter,
+            Terminator::Indented(delimiter) => {
+                line.trim_start_matches('\t').trim_end() == delimiter
+            }
+            Terminator::Prefix(prefix) => line.trim_start().starts_with(prefix),
+        }
+    }
+}
+
+/// Tracks what a command has left open as its lines are read
+#[derive(Debug)]
+struct Reader {
+    /// Language of the block, which decides continuation characters and nesting
+    language: Language,
+    /// Heredocs still to be read, in order
+    heredocs: std::collections::VecDeque<Terminator>,
+    /// Quote character of a string still open
+    quote: Option<char>,
+    /// Compound commands or braces still open
+    depth: usize,
+    /// The last line asked for the next one
+    continued: bool,
+}
+
+impl Reader {
+    /// Start reading a command
+    fn new(language: Language) -> Self {
+        Self {
+            language,
+            heredocs: Default::default(),
+            quote: None,
+            depth: 0,
+            continued: false,
+        }
+    }
+
+    /// Whether the command goes on past the lines read so far
+    fn needs_more(&self) -> bool {
+        !self.heredocs.is_empty() || self.quote.is_some() || self.depth > 0 || self.continued
+    }
+
+    /// Read the next line of the command
+    fn feed(&mut self, line: &str) {
+        if let Some(terminator) = self.heredocs.front() {
+            if terminator.matches(line) {
+                self.heredocs.pop_front();
+            }
+            return;
+        }
+
+        let powershell = self.language == Language::PowerShell;
+        let escape = if powershell { '`' } else { '\\' };
+        let mut code = String::new();
+        let mut word = String::new();
+        let mut command_position = true;
+        let mut chars = line.char_indices().peekable();
+
+        while let Some((i, c)) = chars.next() {
+            if let Some(quote) = self.quote {
+                if c == escape && quote != '\'' {
+                    code.push(c);
+                    if let Some((_, next)) = chars.next() {
+                        code.push(next);
+                    }
+                    continue;
+                } else if c == quote {
+                    self.quote = None;
+                }
+                code.push(c);
+                continue;
+            }
+
+            match c {
+                '\'' | '"' => self.quote = Some(c),
+                c if c == escape => {
+                    code.push(c);
+                    if let Some((_, next)) = chars.next() {
+                        code.push(next);
+                        word.push(next);
+                    }
+                    continue;
+                }
+                // A comment starts a word
+                '#' if word.is_empty() => break,
+                // A here-string, which ends on the same line
+                '<' if !powershell && line[i..].starts_with("<<<") => {
+                    code.push_str("<<<");
+                    chars.next();
+                    chars.next();
+                    continue;
+                }
+                '<' if !powershell && line[i..].starts_with("<<") => {
+                    if let Some((terminator, len)) = heredoc(&line[i..]) {
+                        self.heredocs.push_back(terminator);
+                        while chars.peek().is_some_and(|(j, _)| *j < i + len) {
+                            chars.next();
+                        }
+                        code.push_str(&line[i..i + len]);
+                        continue;
+                    }
+                }
+                '{' | '(' if powershell => self.depth += 1,
+                '}' | ')' if powershell => self.depth = self.depth.saturating_sub(1),
+                _ => {}
+            }
+            code.push(c);
+
+            if c.is_whitespace() || c == ';' || c == '&' || c == '|' {
+                if !powershell {
+                    command_position = self.shell_word(&word, command_position);
+                }
+                word.clear();
+                if c == ';' || c == '&' || c == '|' {
+                    command_position = true;
+                }
+            } else {
+                word.push(c);
+            }
+        }
+        if !powershell && self.quote.is_none() {
+            self.shell_word(&word, command_position);
+        }
+
+        if powershell && line.trim_end().ends_with("@\"") {
+            self.heredocs.push_back(Terminator::Prefix("\"@"));
+        } else if powershell && line.trim_end().ends_with("@'") {
+            self.heredocs.push_back(Terminator::Prefix("'@"));
+        }
+
+        let code = code.trim_end();
+        self.continued = self.quote.is_none()
+            && (code.ends_with(escape)
+                || code.ends_with('|')
+                || code.ends_with("&&")
+                || (!powershell && code.ends_with("||")));
+    }
+
+    /// Count a finished shell word towards nesting
+    ///
+    /// Returns whether the next word is in command position.
+    fn shell_word(&mut self, word: &str, command_position: bool) -> bool {
+        if word.is_empty() {
+            return command_position;
+        }
+        if command_position {
+            if OPENERS.contains(&word) {
+                self.depth += 1;
+            } else if CLOSERS.contains(&word) {
+                self.depth = self.depth.saturating_sub(1);
+            }
+        }
+        // `case` patterns end with `)`, and the commands after them can open blocks
+        command_position && SEPARATORS.contains(&word) || word.ends_with(')')
+    }
+}
+
+/// The heredoc starting at `text`, which begins with `<<`
+///
+/// Returns how the heredoc ends and the length of its operator and delimiter.
+fn heredoc(text: &str) -> Option<(Terminator, usize)> {
+    let rest = text.strip_prefix("<<")?;
+    let (indented, rest) = match rest.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+    let rest = rest.trim_start();
+
+    let (delimiter, len) = match rest.chars().next()? {
+        quote @ ('\'' | '"') => {
+            let end = rest[1..].find(quote)?;
+            (rest[1..=end].to_string(), end + 2)
+        }
+        _ => {
+            let end = rest
+                .find(|c: char| c.is_whitespace() || ";&|<>()".contains(c))
+                .unwrap_or(rest.len());
+            (rest[..end].replace('\\', ""), end)
+        }
+    };
+    if delimiter.is_empty() {
+        return None;
+    }
+
+    let terminator = if indented {
+        Terminator::Indented(delimiter)
+    } else {
+        Terminator::Line(delimiter)
+    };
+    Some((terminator, text.len() - rest.len() + len))
+}
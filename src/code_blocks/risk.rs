@@ -0,0 +1,259 @@
+//! Rough risk levels for commands.
+//!
+//! A command is split at `;`, `&&`, `||`, pipes and newlines, and the program named at
+//! the start of each part is looked up. Wrappers like `sudo`, `env` and `xargs` are
+//! skipped to find the program they run; running as another user is always at least
+//! [`Risk::Caution`]. This is a hint for the reader, not a sandbox.
+
+/// Programs that delete data, end processes or take the machine down
+const DANGER: &[&str] = &[
+    "rm",
+    "rmdir",
+    "dd",
+    "shred",
+    "mkfs",
+    "fdisk",
+    "parted",
+    "wipefs",
+    "truncate",
+    "kill",
+    "killall",
+    "pkill",
+    "shutdown",
+    "reboot",
+    "halt",
+    "poweroff",
+    "del",
+    "rd",
+    "format",
+    "remove-item",
+    "stop-process",
+    "stop-computer",
+    "restart-computer",
+    "format-volume",
+    "clear-disk",
+];
+
+/// Programs that change files, permissions or system state
+const CAUTION: &[&str] = &[
+    "mv",
+    "cp",
+    "ln",
+    "chmod",
+    "chown",
+    "chgrp",
+    "tee",
+    "touch",
+    "mkdir",
+    "install",
+    "systemctl",
+    "service",
+    "launchctl",
+    "crontab",
+    "useradd",
+    "userdel",
+    "passwd",
+    "mount",
+    "umount",
+    "set-content",
+    "add-content",
+    "out-file",
+    "new-item",
+    "move-item",
+    "copy-item",
+    "rename-item",
+    "set-itemproperty",
+    "set-executionpolicy",
+    "install-module",
+    "restart-service",
+    "stop-service",
+];
+
+/// Programs that only change things with one of the subcommands listed for them
+const SUBCOMMANDS: &[(&[&str], &[&str])] = &[
+    (
+        &[
+            "apt", "apt-get", "yum", "dnf", "zypper", "apk", "brew", "snap", "winget", "choco",
+            "pip", "pip3", "npm", "yarn", "pnpm", "cargo", "gem", "go",
+        ],
+        &[
+            "install",
+            "uninstall",
+            "remove",
+            "purge",
+            "add",
+            "upgrade",
+            "update",
+            "publish",
+        ],
+    ),
+    (&["pacman"], &["-s", "-r", "-u"]),
+    (
+        &["git"],
+        &[
+            "commit", "push", "pull", "merge", "rebase", "reset", "checkout", "switch", "stash",
+            "clean", "rm", "mv", "restore", "tag",
+        ],
+    ),
+    (
+        &["docker", "podman", "kubectl", "helm"],
+        &[
+            "rm",
+            "rmi",
+            "run",
+            "stop",
+            "kill",
+            "prune",
+            "delete",
+            "apply",
+            "install",
+            "uninstall",
+            "exec",
+        ],
+    ),
+    (&["sed", "perl"], &["-i"]),
+    (&["curl", "wget"], &["-o", "--output"]),
+];
+
+/// Patterns that make a command dangerous wherever they appear
+const DANGER_PATTERNS: &[&str] = &[
+    "git push --force",
+    "git push -f",
+    "git reset --hard",
+    "git clean -f",
+    "git clean -xf",
+    "git clean -df",
+    "| sh",
+    "|sh",
+    "| bash",
+    "|bash",
+    "| sudo",
+    "> /dev/sd",
+    ">/dev/sd",
+    "| iex",
+    "invoke-expression",
+    ":(){",
+];
+
+/// Programs that run the rest of the line as a command
+const WRAPPERS: &[&str] = &[
+    "env", "time", "nohup", "nice", "xargs", "exec", "command", "then", "do", "else",
+];
+
+/// Programs that run a command as another user
+const ELEVATE: &[&str] = &["sudo", "doas", "su", "runas", "pkexec"];
+
+/// How careful to be before running a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Risk {
+    /// Only reads or prints
+    Safe,
+    /// Changes files, packages or system settings
+    Caution,
+    /// Deletes data, ends processes or affects the whole machine
+    Danger,
+}
+
+impl Risk {
+    /// Classify a command
+    pub fn of(command: &str) -> Risk {
+        let lower = command.to_lowercase();
+        if DANGER_PATTERNS
+            .iter()
+            .any(|pattern| lower.contains(pattern))
+        {
+            return Risk::Danger;
+        }
+
+        let mut risk = if redirects_to_file(&lower) {
+            Risk::Caution
+        } else {
+            Risk::Safe
+        };
+        for part in lower.split(['\n', ';', '|', '&', '(', ')', '`']) {
+            risk = risk.max(Self::of_simple(part));
+        }
+        risk
+    }
+
+    /// Classify a command without separators
+    fn of_simple(part: &str) -> Risk {
+        let mut words = part
+            .split_whitespace()
+            // Variable assignments before the program
+            .skip_while(|word| word.contains('=') && !word.starts_with('-'))
+            .peekable();
+        let mut elevated = false;
+        while let Some(word) = words.peek() {
+            if ELEVATE.contains(word) {
+                elevated = true;
+            } else if !WRAPPERS.contains(word) && !word.starts_with('-') {
+                break;
+            }
+            words.next();
+        }
+
+        let Some(program) = words.next() else {
+            return if elevated { Risk::Caution } else { Risk::Safe };
+        };
+        let program = program.rsplit(['/', '\\']).next().unwrap_or(program);
+        let program = program.strip_suffix(".exe").unwrap_or(program);
+        let args: Vec<&str> = words.collect();
+
+        let risk = if DANGER.contains(&program) || program.starts_with("mkfs.") {
+            Risk::Danger
+        } else if CAUTION.contains(&program)
+            || SUBCOMMANDS.iter().any(|(programs, subcommands)| {
+                programs.contains(&program)
+                    && args.iter().any(|arg| {
+                        subcommands
+                            .iter()
+                            .any(|sub| arg == sub || (sub.starts_with('-') && arg.starts_with(sub)))
+                    })
+            })
+        {
+            Risk::Caution
+        } else {
+            Risk::Safe
+        };
+
+        if elevated {
+            risk.max(Risk::Caution)
+        } else {
+            risk
+        }
+    }
+
+    /// Short label shown before a command
+    pub fn badge(self) -> &'static str {
+        match self {
+            Risk::Safe => "[safe]",
+            Risk::Caution => "[caution]",
+            Risk::Danger => "[danger]",
+        }
+    }
+}
+
+/// Whether a command writes output to a file, other than `/dev/null` or another stream
+fn redirects_to_file(command: &str) -> bool {
+    let mut quote = None;
+    let mut chars = command.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '>') => {
+                let rest = command[i..].trim_start_matches('>').trim_start();
+                if !rest.starts_with('&') && !rest.starts_with("/dev/null") && !rest.is_empty() {
+                    return true;
+                }
+                while chars.peek().is_some_and(|(_, c)| *c == '>') {
+                    chars.next();
+                }
+            }
+            _ => {}
+        }
+    }
+    false
+}
@@ -0,0 +1,250 @@
+//! Choosing which commands from a response to run.
+//!
+//! The menu lists every command found in a response's shell, console and PowerShell
+//! blocks with a [`Risk`] badge. From it a command can be run, edited and then run,
+//! copied to the clipboard, or explained by the model. Before running commands that
+//! aren't safe, including as part of `all`, they are listed and the user is asked once.
+
+use crate::api_client::ApiClient;
+use crate::cli::send_tracked;
+use crate::clipboard::{self, Copied};
+use crate::code_blocks::{Risk, ShellCommand};
+use crate::error::{validation_err, AppError, Result};
+use crate::personalization::Personalization;
+use crate::repl::cancellable;
+use crate::util;
+use rustyline::error::ReadlineError;
+
+/// Prompt shown while the menu is open
+const PROMPT: &str = "Run";
+
+/// What the user chose in the menu
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Run these commands, by 0-based index
+    Run(Vec<usize>),
+    /// Run every command and close the menu
+    All,
+    /// Edit a command and run the result
+    Edit(usize),
+    /// Copy a command to the clipboard
+    Copy(usize),
+    /// Ask the model what a command does
+    Explain(usize),
+    /// Show the list again
+    List,
+    /// Close the menu
+    Done,
+}
+
+impl Action {
+    /// Read a choice such as `2`, `1,3`, `all`, `e 2`, `c2` or `x 1` out of `count` commands
+    pub fn parse(input: &str, count: usize) -> Result<Action> {
+        let input = input.trim().to_lowercase();
+        match input.as_str() {
+            "" | "q" | "quit" | "n" | "no" | "none" | "done" => return Ok(Action::Done),
+            "a" | "all" => return Ok(Action::All),
+            "?" | "l" | "list" | "help" => return Ok(Action::List),
+            _ => {}
+        }
+
+        let action: Option<fn(usize) -> Action> = match input.chars().next() {
+            Some('e') => Some(Action::Edit),
+            Some('c') => Some(Action::Copy),
+            Some('x') => Some(Action::Explain),
+            _ => None,
+        };
+        match action {
+            Some(action) => {
+                let n = input[1..]
+                    .trim()
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|n| (1..=count).contains(n))
+                    .ok_or_else(|| {
+                        validation_err(format!("Choose a command from 1 to {}", count))
+                    })?;
+                Ok(action(n - 1))
+            }
+            None => util::parse_selection(&input, count).map(Action::Run),
+        }
+    }
+}
+
+/// Commands from a response, offered for running
+#[derive(Debug, Clone)]
+pub struct CommandMenu {
+    commands: Vec<ShellCommand>,
+}
+
+impl CommandMenu {
+    /// Menu of the commands in `response`, or `None` if it has none
+    pub fn new(response: &str) -> Option<Self> {
+        let commands = util::extract_commands(response);
+        (!commands.is_empty()).then_some(Self { commands })
+    }
+
+    /// Print the numbered list of commands with their risk
+    pub fn print(&self) {
+        println!("\nCommands in the response:");
+        for (i, command) in self.commands.iter().enumerate() {
+            if let Some(comment) = &command.comment {
+                for line in comment.lines() {
+                    println!("               # {}", line);
+                }
+            }
+            let mut lines = command.text.lines();
+            println!(
+                "  {:>2}. {:<9}  {}",
+                i + 1,
+                command.risk().badge(),
+                lines.next().unwrap_or_default()
+            );
+            for line in lines {
+                println!("               {}", line);
+            }
+        }
+        println!(
+            "Enter numbers to run (e.g. 1,3 or all), e<n> to edit and run, c<n> to copy, \
+             x<n> to explain, or press Enter when done."
+        );
+    }
+
+    /// Show the menu and act on choices until the user is done
+    ///
+    /// Explanations are requested without the conversation, so they don't become part
+    /// of it.
+    pub async fn run(
+        &mut self,
+        api_client: &ApiClient,
+        personalization: &Personalization,
+    ) -> Result<()> {
+        self.print();
+        loop {
+            // Nothing more to read ends the menu, like pressing Enter
+            let input = util::prompt_input(PROMPT)?;
+            let action = match Action::parse(&input, self.commands.len()) {
+                Ok(action) => action,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    continue;
+                }
+            };
+            match action {
+                Action::Done => return Ok(()),
+                Action::List => self.print(),
+                Action::All => {
+                    self.run_chosen(&(0..self.commands.len()).collect::<Vec<_>>())?;
+                    return Ok(());
+                }
+                Action::Run(chosen) => self.run_chosen(&chosen)?,
+                Action::Edit(i) => {
+                    if self.edit(i)? {
+                        self.run_chosen(&[i])?;
+                    }
+                }
+                Action::Copy(i) => match clipboard::copy(&self.commands[i].text)? {
+                    Copied::Clipboard => println!("Copied command {} to the clipboard.", i + 1),
+                    Copied::File(path) => println!(
+                        "The clipboard isn't available here; saved command {} to {}",
+                        i + 1,
+                        path.display()
+                    ),
+                },
+                Action::Explain(i) => {
+                    match cancellable(explain(api_client, personalization, &self.commands[i])).await
+                    {
+                        Ok(explanation) => println!("\n{}", util::format_response(&explanation)),
+                        Err(AppError::Cancelled) => println!("\nRequest cancelled."),
+                        Err(e) => eprintln!("Error: {}", e),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Let the user change command `i`
+    ///
+    /// Returns whether there is a command to run; clearing the line cancels.
+    fn edit(&mut self, i: usize) -> Result<bool> {
+        let command = &mut self.commands[i];
+        let mut editor = rustyline::DefaultEditor::new()?;
+        let edited = match editor.readline_with_initial("edit> ", (&command.text, "")) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        let edited = edited.trim();
+        if edited.is_empty() {
+            println!("Edit cancelled.");
+            return Ok(false);
+        }
+        command.text = edited.to_string();
+        Ok(true)
+    }
+
+    /// Run the chosen commands in order
+    ///
+    /// When any of them isn't safe, those are listed and the user is asked once before
+    /// any command runs.
+    fn run_chosen(&self, chosen: &[usize]) -> Result<()> {
+        let risky: Vec<usize> = chosen
+            .iter()
+            .copied()
+            .filter(|i| self.commands[*i].risk() > Risk::Safe)
+            .collect();
+        if !risky.is_empty() {
+            println!("\nThese commands can change your system:");
+            for i in &risky {
+                let command = &self.commands[*i];
+                println!(
+                    "  {:>2}. {:<9}  {}",
+                    i + 1,
+                    command.risk().badge(),
+                    command.text.lines().next().unwrap_or_default()
+                );
+            }
+            let question = if chosen.len() == 1 {
+                "Run it?"
+            } else {
+                "Run the chosen commands?"
+            };
+            if !util::prompt_yes_no(question, false)? {
+                println!("Nothing was run.");
+                return Ok(());
+            }
+        }
+
+        for i in chosen {
+            let command = &self.commands[*i];
+            println!("\n$ {}", command.text.lines().next().unwrap_or_default());
+            match util::execute_command(command) {
+                Ok(output) => {
+                    println!("┌── output ────────────────────────────────────────────────");
+                    for line in output.lines() {
+                        println!("│ {}", line);
+                    }
+                    println!("└{}", "─".repeat(60));
+                }
+                Err(e) => eprintln!("Error executing command: {}", e),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Ask the model what a command does
+async fn explain(
+    api_client: &ApiClient,
+    personalization: &Personalization,
+    command: &ShellCommand,
+) -> Result<String> {
+    let query = format!(
+        "Explain briefly what this command does, part by part, and anything it changes or \
+         could break:\n```\n{}\n```",
+        command.text
+    );
+    let completion = send_tracked(api_client, &[], &query, personalization, false).await?;
+    Ok(completion.content)
+}
@@ -1,9 +1,22 @@
-use crate::error::{AppError, Result};
-use config::{Environment, File};
-use dirs::home_dir;
+use crate::error::{validation_err, AppError, Result};
+use crate::personalization::PROMPT_VARIABLES;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 
+mod document;
+mod layers;
+mod paths;
+mod schema;
+
+pub use document::{validate_contents, ConfigDocument};
+pub use layers::{ConfigSource, ENV_PREFIX};
+pub use paths::{
+    cache_dir, config_dir, data_dir, find_project_file, legacy_config_file, legacy_dir,
+    legacy_notice, migrate_legacy, state_dir, PROJECT_FILE,
+};
+pub use schema::CURRENT_CONFIG_VERSION;
+
 /// Supported API providers
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
 #[serde(rename_all = "lowercase")]
@@ -13,9 +26,206 @@ pub enum Provider {
     OpenRouter,
 }
 
+impl Provider {
+    /// All supported providers
+    pub const ALL: [Provider; 2] = [Provider::OpenAI, Provider::OpenRouter];
+
+    /// Identifier used in configuration files and on the command line
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Provider::OpenAI => "openai",
+            Provider::OpenRouter => "openrouter",
+        }
+    }
+
+    /// Name of the configuration key holding this provider's API key
+    pub fn api_key_field(&self) -> &'static str {
+        match self {
+            Provider::OpenAI => "openai_api_key",
+            Provider::OpenRouter => "openrouter_api_key",
+        }
+    }
+}
+
+impl std::fmt::Display for Provider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Provider::OpenAI => write!(f, "OpenAI"),
+            Provider::OpenRouter => write!(f, "OpenRouter"),
+        }
+    }
+}
+
+impl std::str::FromStr for Provider {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|provider| provider.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                AppError::Validation(format!(
+                    "Unknown provider '{}'. Expected 'openai' or 'openrouter'",
+                    s
+                ))
+            })
+    }
+}
+
+/// Where API keys are kept when they are not set in the configuration or environment
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum CredentialBackend {
+    /// Keys only come from the configuration file or environment
+    #[default]
+    Config,
+    /// Linux Secret Service (GNOME Keyring, KWallet) over D-Bus
+    SecretService,
+    /// A file encrypted with an age passphrase
+    EncryptedFile,
+    /// The output of a command such as `pass show openai`
+    Command,
+}
+
+impl CredentialBackend {
+    /// All supported backends
+    pub const ALL: [CredentialBackend; 4] = [
+        CredentialBackend::Config,
+        CredentialBackend::SecretService,
+        CredentialBackend::EncryptedFile,
+        CredentialBackend::Command,
+    ];
+
+    /// Identifier used in configuration files and on the command line
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CredentialBackend::Config => "config",
+            CredentialBackend::SecretService => "secret-service",
+            CredentialBackend::EncryptedFile => "encrypted-file",
+            CredentialBackend::Command => "command",
+        }
+    }
+}
+
+impl std::str::FromStr for CredentialBackend {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|backend| backend.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                AppError::Validation(format!(
+                    "Unknown credential backend '{}'. Expected one of: {}",
+                    s,
+                    Self::ALL.map(|backend| backend.as_str()).join(", ")
+                ))
+            })
+    }
+}
+
+/// Settings for storing API keys outside the configuration file
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct CredentialsConfig {
+    /// Backend used to look up keys that are not set in the configuration
+    pub backend: CredentialBackend,
+
+    /// Location of the encrypted credentials file
+    pub file: PathBuf,
+
+    /// Command printing the OpenAI API key (for the `command` backend)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub openai_command: Option<String>,
+
+    /// Command printing the OpenRouter API key (for the `command` backend)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub openrouter_command: Option<String>,
+}
+
+impl Default for CredentialsConfig {
+    fn default() -> Self {
+        Self {
+            backend: CredentialBackend::default(),
+            file: data_dir().join("credentials.age"),
+            openai_command: None,
+            openrouter_command: None,
+        }
+    }
+}
+
+impl CredentialsConfig {
+    /// Command printing the API key for a provider, if one is configured
+    pub fn command(&self, provider: &Provider) -> Option<&str> {
+        match provider {
+            Provider::OpenAI => self.openai_command.as_deref(),
+            Provider::OpenRouter => self.openrouter_command.as_deref(),
+        }
+    }
+}
+
+/// Price of a model in US dollars per million tokens
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct ModelPrice {
+    /// Price per million prompt tokens
+    pub prompt: f64,
+
+    /// Price per million completion tokens
+    pub completion: f64,
+}
+
+impl ModelPrice {
+    /// Cost of a request with the given token counts
+    pub fn cost(&self, prompt_tokens: u64, completion_tokens: u64) -> f64 {
+        (prompt_tokens as f64 * self.prompt + completion_tokens as f64 * self.completion)
+            / 1_000_000.0
+    }
+}
+
+/// Monthly spending limits in US dollars
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct BudgetConfig {
+    /// Warn before each request once this much has been spent this month
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub soft_limit: Option<f64>,
+
+    /// Refuse to send requests once this much has been spent this month
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hard_limit: Option<f64>,
+}
+
+/// When interactive mode summarizes earlier turns of a conversation
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct CompactionConfig {
+    /// Fraction of the context window, after reserving `max_tokens`, that the
+    /// conversation may fill before it is compacted automatically; 0 disables it
+    pub threshold: f64,
+
+    /// Number of recent turns kept verbatim when compacting
+    pub keep_turns: usize,
+}
+
+impl Default for CompactionConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 0.8,
+            keep_turns: 2,
+        }
+    }
+}
+
 /// Configuration for the Chris Terminal application
+///
+/// Every field falls back to its default when missing, so new options can be added
+/// without breaking existing files.
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
 pub struct Config {
+    /// Version of the configuration schema the file was written for
+    pub config_version: u32,
+
     /// Provider to use (OpenAI or OpenRouter)
     pub provider: Provider,
 
@@ -47,52 +257,149 @@ pub struct Config {
     pub store_history: bool,
 
     /// Path to the history file
-    #[serde(default = "default_history_file")]
     pub history_file: PathBuf,
 
     /// Log level (error, warn, info, debug, trace)
     pub log_level: String,
-}
 
-/// Get the default Chris directory in the user's home folder
-fn get_chris_dir() -> PathBuf {
-    home_dir()
-        .unwrap_or_else(|| PathBuf::from("."))
-        .join(".chris")
-}
+    /// Sampling temperature for completions, between 0 and 2
+    pub temperature: f64,
 
-/// Default function for the history file (used by Serde)
-fn default_history_file() -> PathBuf {
-    get_chris_dir().join("history")
-}
+    /// Nucleus sampling probability mass, between 0 and 1
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+
+    /// Sequences where the model stops generating (at most 4)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+
+    /// Seed for best-effort deterministic sampling
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+
+    /// Penalty for tokens that already appeared, between -2 and 2
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f64>,
+
+    /// Penalty proportional to how often tokens appeared, between -2 and 2
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f64>,
+
+    /// Custom system prompt replacing the built-in one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<String>,
+
+    /// Profile applied when none is given on the command line
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_profile: Option<String>,
+
+    /// Where API keys are stored when they are not set above
+    pub credentials: CredentialsConfig,
+
+    /// Prices of models by name, used to estimate the cost of requests
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub pricing: BTreeMap<String, ModelPrice>,
+
+    /// How long the list of available models is cached, in hours
+    pub models_cache_ttl: u64,
+
+    /// Context window sizes in tokens by model name, for models the built-in table
+    /// doesn't know or gets wrong
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub context_windows: BTreeMap<String, usize>,
+
+    /// Monthly spending limits
+    pub budget: BudgetConfig,
+
+    /// Summarizing long conversations
+    pub compaction: CompactionConfig,
+
+    /// Named profiles that override the settings above
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Profile>,
 
-/// Get the default path for the configuration file
-fn get_config_path() -> PathBuf {
-    get_chris_dir().join("config.toml")
+    /// Name of the profile currently applied, if any
+    #[serde(skip)]
+    pub active_profile: Option<String>,
+
+    /// Where each value was set, keyed by dotted path
+    #[serde(skip)]
+    origins: BTreeMap<String, ConfigSource>,
+
+    /// Layers this configuration was built from, used to rebuild it
+    #[serde(skip)]
+    layers: layers::Layers,
 }
 
-/// Create directories necessary for config file if they don't exist
-fn ensure_config_dir_exists() -> Result<()> {
-    let config_path = get_config_path();
-    let config_dir = config_path
-        .parent()
-        .ok_or_else(|| AppError::Config("Could not determine config directory".to_string()))?;
+/// A named set of overrides that can be selected with `--profile`
+///
+/// Every field is optional; unset fields fall back to the top-level configuration.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct Profile {
+    /// Provider to use for this profile
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<Provider>,
 
-    if !config_dir.exists() {
-        std::fs::create_dir_all(config_dir).map_err(AppError::Io)?;
-    }
+    /// Model to use with the profile's provider
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
 
-    Ok(())
+    /// Maximum number of tokens in the completion
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<usize>,
+
+    /// Sampling temperature for completions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+
+    /// Nucleus sampling probability mass
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+
+    /// Sequences where the model stops generating
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+
+    /// Seed for best-effort deterministic sampling
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+
+    /// Penalty for tokens that already appeared
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f64>,
+
+    /// Penalty proportional to how often tokens appeared
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f64>,
+
+    /// System prompt for this profile
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<String>,
+
+    /// Whether to enable personalization features
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enable_personalization: Option<bool>,
+}
+
+/// Default location of the history file
+fn default_history_file() -> PathBuf {
+    state_dir().join("history")
+}
+
+/// Default sampling temperature
+fn default_temperature() -> f64 {
+    0.7
 }
 
 impl Default for Config {
     fn default() -> Self {
         // Default is OpenRouter with deepseek-r1:free model
         Self {
+            config_version: CURRENT_CONFIG_VERSION,
             provider: Provider::OpenRouter,
-            openai_api_key: std::env::var("OPENAI_API_KEY").unwrap_or_default(),
+            openai_api_key: String::new(),
             openai_model: "gpt-3.5-turbo".to_string(),
-            openrouter_api_key: std::env::var("OPENROUTER_API_KEY").unwrap_or_default(),
+            openrouter_api_key: String::new(),
             openrouter_base_url: "https://openrouter.ai/api/v1".to_string(),
             openai_api_url: "https://api.openai.com/v1/chat/completions".to_string(),
             openrouter_model: "deepseek/deepseek-r1:free".to_string(),
@@ -101,64 +408,264 @@ impl Default for Config {
             store_history: true,
             history_file: default_history_file(),
             log_level: "info".to_string(),
+            temperature: default_temperature(),
+            top_p: None,
+            stop: None,
+            seed: None,
+            presence_penalty: None,
+            frequency_penalty: None,
+            system_prompt: None,
+            default_profile: None,
+            credentials: CredentialsConfig::default(),
+            pricing: BTreeMap::new(),
+            models_cache_ttl: 24,
+            context_windows: BTreeMap::new(),
+            budget: BudgetConfig::default(),
+            compaction: CompactionConfig::default(),
+            profiles: BTreeMap::new(),
+            active_profile: None,
+            origins: BTreeMap::new(),
+            layers: layers::Layers::default(),
         }
     }
 }
 
 impl Config {
     /// Load configuration from files and environment variables
+    ///
+    /// Values are layered in increasing order of precedence: defaults, the user
+    /// configuration file, the nearest project `.chris.toml`, the file given with
+    /// `--config`, the default profile and `CHRIS_*` environment variables.
     pub fn load(config_path: Option<&Path>) -> Result<Self> {
         // Create default config if it doesn't exist
         Self::create_default_if_missing()?;
 
-        // Create a new builder using config::builder
-        let mut builder = config::Config::builder();
+        let mut layers = layers::Layers::default();
 
-        // Add default config file
-        builder = builder.add_source(File::from(get_config_path()).required(false));
+        let user_path = Self::active_config_path();
+        if user_path.exists() {
+            layers
+                .files
+                .push((user_path.clone(), read_layer(&user_path)?));
+        }
+
+        // Settings for the project in the current directory. These are never
+        // upgraded in place, since the file belongs to the repository.
+        let project_path = std::env::current_dir()
+            .ok()
+            .and_then(|dir| find_project_file(&dir));
+        if let Some(path) = project_path {
+            let value = toml::from_str(&std::fs::read_to_string(&path)?).map_err(|e| {
+                AppError::Config(format!("Invalid TOML in {}: {}", path.display(), e))
+            })?;
+            schema::warn_unknown_keys(&value, &path);
+            let value = layers::restrict_project_layer(&path, value);
+            layers.files.push((path, value));
+        }
 
         // If a custom config path is provided, use it
         if let Some(path) = config_path {
-            builder = builder.add_source(File::from(path).required(true));
+            if !path.exists() {
+                return Err(AppError::Config(format!(
+                    "Configuration file {} not found",
+                    path.display()
+                )));
+            }
+            layers.files.push((path.to_path_buf(), read_layer(path)?));
         }
 
-        // Add environment variables
-        builder = builder.add_source(Environment::default().try_parsing(true));
-
-        // Build the configuration
-        let config = builder
-            .build()
-            .map_err(|e| AppError::Config(e.to_string()))?;
+        layers.capture_env();
+        layers.warn_unknown_env();
 
-        // Deserialize into our config struct
-        let cfg: Config = config
-            .try_deserialize()
-            .map_err(|e| AppError::Config(e.to_string()))?;
+        layers.build(None)
+    }
 
-        // Validate required fields based on provider
-        match cfg.provider {
+    /// Validate that the settings required by the selected provider are present
+    pub fn validate(&self) -> Result<()> {
+        match self.provider {
             Provider::OpenAI => {
-                if cfg.openai_api_key.is_empty() {
-                    return Err(AppError::Config("OpenAI API key is required. Set it in the config file or use the OPENAI_API_KEY environment variable.".to_string()));
+                if self.openai_api_key.is_empty() {
+                    return Err(AppError::Config("OpenAI API key is required. Run `chris auth login`, or set the CHRIS_API_KEY environment variable.".to_string()));
                 }
             }
             Provider::OpenRouter => {
-                if cfg.openrouter_api_key.is_empty() {
-                    return Err(AppError::Config("OpenRouter API key is required. Set it in the config file or use the OPENROUTER_API_KEY environment variable.".to_string()));
+                if self.openrouter_api_key.is_empty() {
+                    return Err(AppError::Config("OpenRouter API key is required. Run `chris auth login`, or set the CHRIS_API_KEY environment variable.".to_string()));
                 }
             }
         }
 
-        Ok(cfg)
+        self.validate_parameters()?;
+
+        if let Some(prompt) = &self.system_prompt {
+            for name in crate::template::variables(prompt) {
+                if !PROMPT_VARIABLES.contains(&name) {
+                    return Err(validation_err(format!(
+                        "Unknown variable '{{{{{}}}}}' in system_prompt. Available variables: {}",
+                        name,
+                        PROMPT_VARIABLES.join(", ")
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that generation parameters, including those in profiles, are in range
+    pub fn validate_parameters(&self) -> Result<()> {
+        check_parameters(
+            "",
+            Some(self.temperature),
+            self.top_p,
+            self.stop.as_deref(),
+            self.presence_penalty,
+            self.frequency_penalty,
+        )?;
+
+        for (name, limit) in [
+            ("budget.soft_limit", self.budget.soft_limit),
+            ("budget.hard_limit", self.budget.hard_limit),
+        ] {
+            if limit.is_some_and(|limit| limit < 0.0) {
+                return Err(validation_err(format!("{} cannot be negative", name)));
+            }
+        }
+
+        if !(0.0..=1.0).contains(&self.compaction.threshold) {
+            return Err(validation_err(
+                "compaction.threshold must be between 0 and 1",
+            ));
+        }
+
+        for (name, profile) in &self.profiles {
+            check_parameters(
+                &format!("profiles.{}.", name),
+                profile.temperature,
+                profile.top_p,
+                profile.stop.as_deref(),
+                profile.presence_penalty,
+                profile.frequency_penalty,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Return a copy of this configuration with a profile applied
+    ///
+    /// When `name` is `None` the `default_profile` is used, if one is set. The
+    /// configuration is rebuilt from its layers, so environment variables and
+    /// command line overrides still take precedence over the profile.
+    pub fn with_profile(&self, name: Option<&str>) -> Result<Self> {
+        self.rebuild_layers()?.build(name)
+    }
+
+    /// Return a copy of this configuration switched to another profile mid-session
+    ///
+    /// Command line flags still take precedence over the profile. Changes made with
+    /// interactive commands, such as `/model`, are dropped in favour of the profile.
+    pub fn switch_profile(&self, name: &str) -> Result<Self> {
+        let mut layers = self.rebuild_layers()?;
+        layers.overrides.retain(
+            |(source, _)| !matches!(source, ConfigSource::CommandLine(flag) if flag.starts_with('/')),
+        );
+        layers.build(Some(name))
+    }
+
+    /// Command line flags overriding this configuration, in the order given
+    pub fn flags(&self) -> Vec<&str> {
+        self.layers
+            .overrides
+            .iter()
+            .filter_map(|(source, _)| match source {
+                ConfigSource::CommandLine(flag) if flag.starts_with("--") => Some(flag.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Override a dotted key with a value from the command line
+    pub fn apply_override(
+        &mut self,
+        key: &str,
+        value: toml::Value,
+        source: ConfigSource,
+    ) -> Result<()> {
+        let mut layers = self.rebuild_layers()?;
+        layers
+            .overrides
+            .push((source, layers::table_for(key, value)));
+        *self = layers.build(self.active_profile.as_deref())?;
+
+        Ok(())
+    }
+
+    /// Where the value of a dotted key came from
+    pub fn origin(&self, key: &str) -> ConfigSource {
+        self.origins.get(key).cloned().unwrap_or_default()
+    }
+
+    /// Every value in the configuration with its dotted key and origin
+    pub fn entries(&self) -> Result<Vec<(String, toml::Value, ConfigSource)>> {
+        let value = toml::Value::try_from(self)?;
+        Ok(layers::leaves(&value)
+            .into_iter()
+            .map(|(key, value)| {
+                let origin = self.origin(&key);
+                (key, value.clone(), origin)
+            })
+            .collect())
+    }
+
+    /// Layers to rebuild this configuration from
+    ///
+    /// Configurations that were not loaded use their current values as defaults.
+    fn rebuild_layers(&self) -> Result<layers::Layers> {
+        if self.layers.is_loaded() {
+            return Ok(self.layers.clone());
+        }
+
+        Ok(layers::Layers {
+            defaults: Some(toml::Value::try_from(self)?),
+            ..Default::default()
+        })
+    }
+
+    /// Get the value of a dotted key (e.g. `profiles.deep.model`), if it is set
+    pub fn get_value(&self, key: &str) -> Result<Option<toml::Value>> {
+        let value = toml::Value::try_from(self)?;
+        Ok(document::lookup(&value, key).cloned())
+    }
+
+    /// Names of all configured profiles, in sorted order
+    pub fn profile_names(&self) -> Vec<&str> {
+        self.profiles.keys().map(String::as_str).collect()
+    }
+
+    /// Get the model for the selected provider
+    pub fn model(&self) -> &str {
+        match self.provider {
+            Provider::OpenAI => &self.openai_model,
+            Provider::OpenRouter => &self.openrouter_model,
+        }
+    }
+
+    /// Set the model for the selected provider
+    pub fn set_model(&mut self, model: &str) {
+        match self.provider {
+            Provider::OpenAI => self.openai_model = model.to_string(),
+            Provider::OpenRouter => self.openrouter_model = model.to_string(),
+        }
     }
 
     /// Create a default configuration file if it doesn't exist
     pub fn create_default_if_missing() -> Result<()> {
-        let config_path = get_config_path();
+        let config_path = Self::active_config_path();
 
         if !config_path.exists() {
             // Create directory structure
-            ensure_config_dir_exists()?;
+            std::fs::create_dir_all(config_dir()).map_err(AppError::Io)?;
 
             // Create default config with OpenRouter and deepseek-r1:free
             let default_config = Config::default();
@@ -181,6 +688,21 @@ impl Config {
         }
     }
 
+    /// Get the URL listing the provider's models
+    ///
+    /// For OpenAI this is derived from the completions URL, so that compatible
+    /// servers configured with `openai_api_url` are queried as well.
+    pub fn get_models_url(&self) -> String {
+        match self.provider {
+            Provider::OpenAI => {
+                let base = self.openai_api_url.trim_end_matches('/');
+                let base = base.strip_suffix("/chat/completions").unwrap_or(base);
+                format!("{}/models", base)
+            }
+            Provider::OpenRouter => format!("{}/models", self.openrouter_base_url),
+        }
+    }
+
     /// Get the API key based on the provider
     pub fn get_api_key(&self) -> String {
         match self.provider {
@@ -189,6 +711,30 @@ impl Config {
         }
     }
 
+    /// Set the API key for a provider
+    pub fn set_api_key(&mut self, provider: &Provider, key: String) {
+        match provider {
+            Provider::OpenAI => self.openai_api_key = key,
+            Provider::OpenRouter => self.openrouter_api_key = key,
+        }
+    }
+
+    /// Describe an API key for display without revealing it
+    fn describe_api_key(&self, key: &str) -> String {
+        if !key.is_empty() {
+            "[REDACTED]".to_string()
+        } else if self.credentials.backend != CredentialBackend::Config {
+            format!("From {}", self.credentials.backend.as_str())
+        } else {
+            "Not set".to_string()
+        }
+    }
+
+    /// Get the configured price of a model, if any
+    pub fn price(&self, model: &str) -> Option<&ModelPrice> {
+        self.pricing.get(model)
+    }
+
     /// Get the site URL (hardcoded)
     pub fn get_site_url(&self) -> String {
         "example.com".to_string()
@@ -201,10 +747,20 @@ impl Config {
 
     /// Gets the default config path
     pub fn get_config_path() -> PathBuf {
-        let mut config_dir = dirs::config_dir().unwrap_or_else(|| PathBuf::from("."));
-        config_dir.push("chris");
-        config_dir.push("config.toml");
-        config_dir
+        config_dir().join("config.toml")
+    }
+
+    /// Gets the config file that is actually read
+    ///
+    /// This is the default config path, unless only a legacy `~/.chris/config.toml`
+    /// exists and has not been migrated yet.
+    pub fn active_config_path() -> PathBuf {
+        let config_path = Self::get_config_path();
+        if config_path.exists() {
+            return config_path;
+        }
+
+        legacy_config_file().unwrap_or(config_path)
     }
 
     /// Save configuration to a file
@@ -224,6 +780,66 @@ impl Config {
     }
 }
 
+/// Maximum number of stop sequences accepted by the APIs
+const MAX_STOP_SEQUENCES: usize = 4;
+
+/// Check generation parameters against the ranges the APIs accept
+///
+/// `prefix` is prepended to key names in error messages.
+fn check_parameters(
+    prefix: &str,
+    temperature: Option<f64>,
+    top_p: Option<f64>,
+    stop: Option<&[String]>,
+    presence_penalty: Option<f64>,
+    frequency_penalty: Option<f64>,
+) -> Result<()> {
+    let ranges = [
+        ("temperature", temperature, 0.0, 2.0),
+        ("top_p", top_p, 0.0, 1.0),
+        ("presence_penalty", presence_penalty, -2.0, 2.0),
+        ("frequency_penalty", frequency_penalty, -2.0, 2.0),
+    ];
+    for (name, value, min, max) in ranges {
+        if let Some(value) = value {
+            if !(min..=max).contains(&value) {
+                return Err(validation_err(format!(
+                    "{}{} must be between {} and {}, got {}",
+                    prefix, name, min, max, value
+                )));
+            }
+        }
+    }
+
+    if let Some(stop) = stop {
+        if stop.len() > MAX_STOP_SEQUENCES {
+            return Err(validation_err(format!(
+                "{}stop accepts at most {} sequences, got {}",
+                prefix,
+                MAX_STOP_SEQUENCES,
+                stop.len()
+            )));
+        }
+        if stop.iter().any(String::is_empty) {
+            return Err(validation_err(format!(
+                "{}stop sequences cannot be empty",
+                prefix
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Read a configuration file as a layer, upgrading it first if needed
+fn read_layer(path: &Path) -> Result<toml::Value> {
+    schema::prepare_file(path)?;
+    let contents = std::fs::read_to_string(path)?;
+
+    toml::from_str(&contents)
+        .map_err(|e| AppError::Config(format!("Invalid TOML in {}: {}", path.display(), e)))
+}
+
 impl std::fmt::Display for Config {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(
@@ -235,13 +851,37 @@ impl std::fmt::Display for Config {
             }
         )?;
 
-        if self.provider == Provider::OpenAI {
-            writeln!(f, "Model: {}", self.openai_model)?;
-        } else {
-            writeln!(f, "Model: {}", self.openrouter_model)?;
+        if let Some(profile) = &self.active_profile {
+            writeln!(f, "Profile: {}", profile)?;
         }
 
+        writeln!(f, "Model: {}", self.model())?;
         writeln!(f, "Max Tokens: {}", self.max_tokens)?;
+        writeln!(f, "Temperature: {}", self.temperature)?;
+        if let Some(top_p) = self.top_p {
+            writeln!(f, "Top P: {}", top_p)?;
+        }
+        if let Some(stop) = &self.stop {
+            writeln!(f, "Stop Sequences: {:?}", stop)?;
+        }
+        if let Some(seed) = self.seed {
+            writeln!(f, "Seed: {}", seed)?;
+        }
+        if let Some(penalty) = self.presence_penalty {
+            writeln!(f, "Presence Penalty: {}", penalty)?;
+        }
+        if let Some(penalty) = self.frequency_penalty {
+            writeln!(f, "Frequency Penalty: {}", penalty)?;
+        }
+        writeln!(
+            f,
+            "System Prompt: {}",
+            if self.system_prompt.is_some() {
+                "Custom"
+            } else {
+                "Default"
+            }
+        )?;
 
         // API connection settings
         if self.provider == Provider::OpenAI {
@@ -249,11 +889,7 @@ impl std::fmt::Display for Config {
             writeln!(
                 f,
                 "OpenAI API Key: {}",
-                if self.openai_api_key.is_empty() {
-                    "Not set"
-                } else {
-                    "[REDACTED]"
-                }
+                self.describe_api_key(&self.openai_api_key)
             )?;
         } else {
             writeln!(f, "API URL: {}", self.get_api_url())?;
@@ -262,11 +898,7 @@ impl std::fmt::Display for Config {
             writeln!(
                 f,
                 "OpenRouter API Key: {}",
-                if self.openrouter_api_key.is_empty() {
-                    "Not set"
-                } else {
-                    "[REDACTED]"
-                }
+                self.describe_api_key(&self.openrouter_api_key)
             )?;
         }
 
@@ -294,6 +926,10 @@ impl std::fmt::Display for Config {
         // System settings
         writeln!(f, "Log Level: {}", self.log_level)?;
 
+        if !self.profiles.is_empty() {
+            writeln!(f, "Profiles: {}", self.profile_names().join(", "))?;
+        }
+
         Ok(())
     }
 }
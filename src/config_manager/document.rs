@@ -0,0 +1,282 @@
+//! Comment-preserving editing of configuration files.
+//!
+//! Used by the `chris config get|set|unset|edit` subcommands so that changing a
+//! single key does not rewrite the user's formatting or drop their comments.
+
+use super::Config;
+use crate::error::{config_err, validation_err, Result};
+use std::path::{Path, PathBuf};
+use toml_edit::{DocumentMut, Item, Table, Value};
+
+/// A configuration file loaded for editing
+#[derive(Debug)]
+pub struct ConfigDocument {
+    /// Location of the file on disk
+    path: PathBuf,
+    /// Parsed document, including comments and whitespace
+    doc: DocumentMut,
+}
+
+impl ConfigDocument {
+    /// Open a configuration file, starting from an empty document if it doesn't exist
+    pub fn open(path: &Path) -> Result<Self> {
+        let doc = if path.exists() {
+            parse_document(&std::fs::read_to_string(path)?)?
+        } else {
+            DocumentMut::new()
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            doc,
+        })
+    }
+
+    /// Path of the underlying file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Get a string value stored directly in the file
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        self.get_item(key)?.as_str()
+    }
+
+    /// Get an item stored directly in the file
+    pub(super) fn get_item(&self, key: &str) -> Option<&Item> {
+        key.split('.')
+            .try_fold(self.doc.as_item(), |item, part| item.get(part))
+    }
+
+    /// Set a top-level value without validating the result
+    pub(super) fn set_raw(&mut self, key: &str, value: impl Into<Value>) {
+        self.doc[key] = toml_edit::value(value);
+    }
+
+    /// Rename a top-level key, keeping its value and comments
+    ///
+    /// Nothing is changed if `from` is missing or `to` already exists.
+    pub(super) fn rename_key(&mut self, from: &str, to: &str) -> bool {
+        let table = self.doc.as_table_mut();
+        if table.contains_key(to) {
+            return false;
+        }
+
+        match table.remove_entry(from) {
+            Some((mut key, item)) => {
+                let decor = key.leaf_decor().clone();
+                key = toml_edit::Key::new(to);
+                *key.leaf_decor_mut() = decor;
+                table.insert_formatted(&key, item);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Contents of the file as a plain TOML value
+    pub(super) fn to_value(&self) -> Result<toml::Value> {
+        toml::from_str(&self.doc.to_string())
+            .map_err(|e| config_err(format!("Invalid TOML: {}", e)))
+    }
+
+    /// Set a dotted key (e.g. `profiles.deep.model`) to the given raw value
+    ///
+    /// The value is parsed as a TOML value when possible and treated as a string
+    /// otherwise. The result is validated against [`Config`] before it is accepted.
+    pub fn set(&mut self, key: &str, raw: &str) -> Result<()> {
+        let parts = split_key(key)?;
+        let typed = raw
+            .parse::<Value>()
+            .ok()
+            .filter(|value| !value.is_inline_table());
+
+        let mut candidates = Vec::new();
+        if let Some(value) = typed.filter(|value| !value.is_str()) {
+            // Whole numbers are also valid for floating point keys
+            if let Some(number) = value.as_integer() {
+                candidates.push(Value::from(number));
+                candidates.push(Value::from(number as f64));
+            } else {
+                candidates.push(value);
+            }
+        }
+        candidates.push(Value::from(raw));
+
+        // The error for the most specific type is the most helpful one to report
+        let mut first_err = None;
+        for value in candidates {
+            let mut doc = self.doc.clone();
+            insert_value(&mut doc, &parts, value)?;
+            match validate_document(&doc, Some(key)) {
+                Ok(()) => {
+                    self.doc = doc;
+                    return Ok(());
+                }
+                Err(e) => {
+                    first_err.get_or_insert(e);
+                }
+            }
+        }
+
+        Err(first_err.unwrap_or_else(|| validation_err(format!("Invalid value for {}", key))))
+    }
+
+    /// Remove a dotted key, reverting it to its default
+    ///
+    /// Returns `false` if the key was not present in the file.
+    pub fn unset(&mut self, key: &str) -> Result<bool> {
+        let parts = split_key(key)?;
+        let (last, parents) = parts
+            .split_last()
+            .ok_or_else(|| config_err("Configuration key cannot be empty"))?;
+
+        let mut table = self.doc.as_table_mut() as &mut dyn toml_edit::TableLike;
+        for part in parents {
+            table = match table.get_mut(part).and_then(Item::as_table_like_mut) {
+                Some(next) => next,
+                None => return Ok(false),
+            };
+        }
+
+        let removed = table.remove(last).is_some();
+        if removed {
+            validate_document(&self.doc, None)?;
+        }
+
+        Ok(removed)
+    }
+
+    /// Write the document back to disk
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&self.path, self.doc.to_string())?;
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for ConfigDocument {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.doc)
+    }
+}
+
+/// Check that file contents parse and produce a valid configuration
+pub fn validate_contents(contents: &str) -> Result<()> {
+    validate_document(&parse_document(contents)?, None)
+}
+
+/// Look up a dotted key in a TOML value
+pub fn lookup<'a>(value: &'a toml::Value, key: &str) -> Option<&'a toml::Value> {
+    key.split('.')
+        .try_fold(value, |current, part| current.as_table()?.get(part))
+}
+
+fn parse_document(contents: &str) -> Result<DocumentMut> {
+    contents
+        .parse::<DocumentMut>()
+        .map_err(|e| config_err(format!("Invalid TOML: {}", e)))
+}
+
+fn split_key(key: &str) -> Result<Vec<&str>> {
+    let parts: Vec<&str> = key.split('.').collect();
+    if parts.iter().any(|part| part.trim().is_empty()) {
+        return Err(validation_err(format!(
+            "Invalid configuration key '{}'",
+            key
+        )));
+    }
+
+    Ok(parts)
+}
+
+fn insert_value(doc: &mut DocumentMut, parts: &[&str], value: Value) -> Result<()> {
+    let (last, parents) = parts
+        .split_last()
+        .ok_or_else(|| config_err("Configuration key cannot be empty"))?;
+
+    let mut table = doc.as_table_mut() as &mut dyn toml_edit::TableLike;
+    for part in parents {
+        let entry = table.entry(part).or_insert_with(|| {
+            let mut new_table = Table::new();
+            new_table.set_implicit(true);
+            Item::Table(new_table)
+        });
+        table = entry.as_table_like_mut().ok_or_else(|| {
+            validation_err(format!("'{}' is not a table and cannot contain keys", part))
+        })?;
+    }
+
+    match table.get_mut(last) {
+        // Keep the existing decoration (comments, spacing) around the value
+        Some(Item::Value(existing)) => {
+            let decor = existing.decor().clone();
+            *existing = value;
+            *existing.decor_mut() = decor;
+        }
+        _ => {
+            table.insert(last, Item::Value(value));
+        }
+    }
+
+    Ok(())
+}
+
+/// Validate a document by layering it over the defaults and deserializing it
+///
+/// When `key` is given it must survive a round trip through [`Config`], which
+/// rejects keys that `Config` does not know about.
+fn validate_document(doc: &DocumentMut, key: Option<&str>) -> Result<()> {
+    let file: toml::Value =
+        toml::from_str(&doc.to_string()).map_err(|e| config_err(format!("Invalid TOML: {}", e)))?;
+
+    let mut merged = toml::Value::try_from(Config::default())?;
+    merge(&mut merged, file);
+
+    let config: Config = merged
+        .try_into()
+        .map_err(|e: toml::de::Error| validation_err(e.message().to_string()))?;
+    config.validate_parameters()?;
+
+    if let Some(key) = key {
+        let round_trip = toml::Value::try_from(&config)?;
+        if lookup(&round_trip, key).is_none() {
+            return Err(validation_err(format!(
+                "Unknown configuration key '{}'",
+                key
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Deserialize file contents layered over the default configuration
+pub(super) fn with_defaults(file: toml::Value) -> Result<Config> {
+    let mut merged = toml::Value::try_from(Config::default())?;
+    merge(&mut merged, file);
+
+    merged
+        .try_into()
+        .map_err(|e: toml::de::Error| validation_err(e.message().to_string()))
+}
+
+/// Recursively merge `overlay` into `base`, with values from `overlay` winning
+pub(super) fn merge(base: &mut toml::Value, overlay: toml::Value) {
+    match (base, overlay) {
+        (toml::Value::Table(base), toml::Value::Table(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
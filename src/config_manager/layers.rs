@@ -0,0 +1,403 @@
+//! Layered loading of the configuration, tracking where each value came from.
+//!
+//! Layers are merged in increasing order of precedence:
+//! defaults, the user configuration file, the project's `.chris.toml`, the file
+//! given with `--config`, the selected profile, environment variables and finally
+//! command line flags.
+
+use super::document::merge;
+use super::schema;
+use super::{Config, Profile, Provider};
+use crate::error::{config_err, AppError, Result};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Prefix of environment variables that override configuration keys
+pub const ENV_PREFIX: &str = "CHRIS_";
+
+/// Separator between nested keys in environment variable names
+const ENV_SEPARATOR: &str = "__";
+
+/// `CHRIS_` variables that are not configuration keys
+const RESERVED_ENV: &[&str] = &["CHRIS_PASSPHRASE"];
+
+/// Provider-neutral environment variables and the per-provider key they map to
+const ENV_ALIASES: &[(&str, &str)] = &[("CHRIS_API_KEY", "api_key"), ("CHRIS_MODEL", "model")];
+
+/// Top-level keys a project `.chris.toml` may set
+///
+/// Project files come with whatever repository is checked out, so they may only
+/// change how the model is used. API keys, endpoints, credential commands and local
+/// paths are left to the user's own configuration.
+const PROJECT_KEYS: &[&str] = &[
+    "provider",
+    "openai_model",
+    "openrouter_model",
+    "max_tokens",
+    "temperature",
+    "top_p",
+    "stop",
+    "seed",
+    "presence_penalty",
+    "frequency_penalty",
+    "system_prompt",
+    "enable_personalization",
+    "default_profile",
+    "profiles",
+];
+
+/// Where a configuration value came from
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum ConfigSource {
+    /// Built-in default
+    #[default]
+    Default,
+    /// A configuration file
+    File(PathBuf),
+    /// The named profile
+    Profile(String),
+    /// An environment variable
+    Environment(String),
+    /// A command line flag
+    CommandLine(String),
+}
+
+impl std::fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigSource::Default => write!(f, "default"),
+            ConfigSource::File(path) => write!(f, "{}", path.display()),
+            ConfigSource::Profile(name) => write!(f, "profile '{}'", name),
+            ConfigSource::Environment(var) => write!(f, "${}", var),
+            ConfigSource::CommandLine(flag) => write!(f, "command line ({})", flag),
+        }
+    }
+}
+
+/// The raw inputs a configuration was built from
+#[derive(Debug, Clone, Default)]
+pub(super) struct Layers {
+    /// Base values; `None` means [`Config::default`]
+    pub defaults: Option<toml::Value>,
+    /// Configuration files, lowest precedence first
+    pub files: Vec<(PathBuf, toml::Value)>,
+    /// Relevant environment variables
+    pub env: Vec<(String, String)>,
+    /// Command line overrides, applied last
+    pub overrides: Vec<(ConfigSource, toml::Value)>,
+}
+
+impl Layers {
+    /// Capture the `CHRIS_` variables and the legacy `<PROVIDER>_API_KEY` variables
+    pub fn capture_env(&mut self) {
+        let legacy: Vec<String> = Provider::ALL
+            .iter()
+            .map(|provider| provider.api_key_field().to_uppercase())
+            .collect();
+
+        self.env = std::env::vars()
+            .filter(|(name, value)| {
+                !value.is_empty()
+                    && ((name.starts_with(ENV_PREFIX) && !RESERVED_ENV.contains(&name.as_str()))
+                        || legacy.contains(name))
+            })
+            .collect();
+        self.env.sort();
+    }
+
+    /// Warn about `CHRIS_` variables that don't name a configuration key
+    pub fn warn_unknown_env(&self) {
+        let to_env = |key: &str| {
+            format!(
+                "{}{}",
+                ENV_PREFIX,
+                key.replace('.', ENV_SEPARATOR).to_uppercase()
+            )
+        };
+
+        for (name, _) in &self.env {
+            let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            if ENV_ALIASES.iter().any(|(alias, _)| alias == name) {
+                continue;
+            }
+
+            let key = key.to_lowercase().replace(ENV_SEPARATOR, ".");
+            let layer = table_for(&key, toml::Value::String(String::new()));
+            for (unknown, suggestion) in schema::unknown_keys(&layer) {
+                match suggestion {
+                    Some(suggestion) => log::warn!(
+                        "Unknown environment variable {}. Did you mean {}?",
+                        to_env(&unknown),
+                        to_env(&suggestion)
+                    ),
+                    None => log::warn!("Unknown environment variable {}", to_env(&unknown)),
+                }
+            }
+        }
+    }
+
+    /// Whether these layers came from loading the configuration
+    pub fn is_loaded(&self) -> bool {
+        self.defaults.is_some()
+            || !self.files.is_empty()
+            || !self.env.is_empty()
+            || !self.overrides.is_empty()
+    }
+
+    /// Merge all layers into a configuration, optionally applying a profile
+    pub fn build(&self, profile: Option<&str>) -> Result<Config> {
+        let defaults = match &self.defaults {
+            Some(defaults) => defaults.clone(),
+            None => toml::Value::try_from(Config::default())?,
+        };
+
+        let mut layers = vec![(ConfigSource::Default, defaults)];
+        layers.extend(
+            self.files
+                .iter()
+                .map(|(path, value)| (ConfigSource::File(path.clone()), value.clone())),
+        );
+        let explicit_env = self.explicit_env_layers()?;
+
+        // The profile can be chosen in a file or the environment
+        let mut merged = merge_layers(&layers);
+        merge_all(&mut merged, &explicit_env);
+        let file_config = deserialize(merged.clone())?;
+        let profile_name = profile
+            .map(str::to_string)
+            .or(file_config.default_profile.clone());
+
+        if let Some(name) = &profile_name {
+            let profile = file_config.profiles.get(name).ok_or_else(|| {
+                let available = file_config.profile_names().join(", ");
+                config_err(format!(
+                    "Unknown profile '{}'. Available profiles: {}",
+                    name,
+                    if available.is_empty() {
+                        "none"
+                    } else {
+                        &available
+                    }
+                ))
+            })?;
+            let provider = profile
+                .provider
+                .clone()
+                .unwrap_or(file_config.provider.clone());
+            layers.push((
+                ConfigSource::Profile(name.clone()),
+                profile_layer(profile, &provider)?,
+            ));
+            merged = merge_layers(&layers);
+            merge_all(&mut merged, &explicit_env);
+        }
+
+        // Provider-neutral variables apply to whichever provider ends up selected
+        merge_all(&mut merged, &self.overrides);
+        let provider = deserialize(merged)?.provider;
+        layers.extend(self.alias_env_layers(&provider));
+        layers.extend(explicit_env);
+        layers.extend(self.overrides.iter().cloned());
+
+        let mut config = deserialize(merge_layers(&layers))?;
+        config.origins = origins(&layers);
+        config.layers = self.clone();
+        config.active_profile = profile_name;
+
+        Ok(config)
+    }
+
+    /// Layers for `CHRIS_*` variables naming configuration keys directly
+    fn explicit_env_layers(&self) -> Result<Vec<(ConfigSource, toml::Value)>> {
+        let mut layers = Vec::new();
+        for (name, raw) in &self.env {
+            let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            if ENV_ALIASES.iter().any(|(alias, _)| alias == name) {
+                continue;
+            }
+
+            let key = key.to_lowercase().replace(ENV_SEPARATOR, ".");
+            let layer = typed_layer(&key, raw).map_err(|e| {
+                let reason = match e {
+                    AppError::Validation(reason) => reason,
+                    e => e.to_string(),
+                };
+                config_err(format!("Invalid value for {}: {}", name, reason))
+            })?;
+
+            layers.push((ConfigSource::Environment(name.clone()), layer));
+        }
+
+        Ok(layers)
+    }
+
+    /// Layers for the legacy and provider-neutral variables, lowest precedence first
+    fn alias_env_layers(&self, provider: &Provider) -> Vec<(ConfigSource, toml::Value)> {
+        let get = |name: &str| {
+            self.env
+                .iter()
+                .find(|(var, _)| var == name)
+                .map(|(_, value)| value.clone())
+        };
+
+        let mut layers = Vec::new();
+        for candidate in Provider::ALL {
+            let var = candidate.api_key_field().to_uppercase();
+            if let Some(value) = get(&var) {
+                layers.push((
+                    ConfigSource::Environment(var),
+                    table_for(candidate.api_key_field(), toml::Value::String(value)),
+                ));
+            }
+        }
+
+        for (alias, suffix) in ENV_ALIASES {
+            if let Some(value) = get(alias) {
+                layers.push((
+                    ConfigSource::Environment(alias.to_string()),
+                    table_for(
+                        &format!("{}_{}", provider.as_str(), suffix),
+                        toml::Value::String(value),
+                    ),
+                ));
+            }
+        }
+
+        layers
+    }
+}
+
+/// Remove the keys a project file is not allowed to set, warning about each one
+pub(super) fn restrict_project_layer(
+    path: &std::path::Path,
+    mut value: toml::Value,
+) -> toml::Value {
+    if let Some(table) = value.as_table_mut() {
+        table.retain(|key, _| {
+            let allowed = PROJECT_KEYS.contains(&key);
+            if !allowed {
+                log::warn!(
+                    "Ignoring '{}' in {}: project files cannot set it",
+                    key,
+                    path.display()
+                );
+            }
+            allowed
+        });
+    }
+
+    value
+}
+
+/// Build a table with a single value at a dotted key
+pub(super) fn table_for(key: &str, value: toml::Value) -> toml::Value {
+    key.rsplit('.').fold(value, |inner, part| {
+        let mut table = toml::map::Map::new();
+        table.insert(part.to_string(), inner);
+        toml::Value::Table(table)
+    })
+}
+
+/// Parse a raw string into a single-key layer, using the most specific valid type
+fn typed_layer(key: &str, raw: &str) -> Result<toml::Value> {
+    let candidates = [
+        raw.parse::<bool>().ok().map(toml::Value::Boolean),
+        raw.parse::<i64>().ok().map(toml::Value::Integer),
+        raw.parse::<f64>().ok().map(toml::Value::Float),
+        parse_array(raw),
+        Some(toml::Value::String(raw.to_string())),
+    ];
+
+    // The error for the most specific type is the most helpful one to report
+    let mut first_err = None;
+    for value in candidates.into_iter().flatten() {
+        let layer = table_for(key, value);
+        match super::document::with_defaults(layer.clone()) {
+            Ok(config) => match config.validate_parameters() {
+                Ok(()) => return Ok(layer),
+                Err(e) => {
+                    first_err.get_or_insert(e);
+                }
+            },
+            Err(e) => {
+                first_err.get_or_insert(e);
+            }
+        }
+    }
+
+    Err(first_err.unwrap_or_else(|| config_err("invalid value")))
+}
+
+/// Parse a TOML array such as `["a", "b"]`
+fn parse_array(raw: &str) -> Option<toml::Value> {
+    let table: toml::Table = toml::from_str(&format!("value = {}", raw)).ok()?;
+    table.get("value").filter(|value| value.is_array()).cloned()
+}
+
+/// Convert a profile into a layer of top-level configuration keys
+fn profile_layer(profile: &Profile, provider: &Provider) -> Result<toml::Value> {
+    let mut value = toml::Value::try_from(profile)?;
+    if let Some(table) = value.as_table_mut() {
+        if let Some(model) = table.remove("model") {
+            table.insert(format!("{}_model", provider.as_str()), model);
+        }
+    }
+
+    Ok(value)
+}
+
+fn merge_layers(layers: &[(ConfigSource, toml::Value)]) -> toml::Value {
+    let mut merged = toml::Value::Table(toml::map::Map::new());
+    merge_all(&mut merged, layers);
+    merged
+}
+
+fn merge_all(merged: &mut toml::Value, layers: &[(ConfigSource, toml::Value)]) {
+    for (_, value) in layers {
+        merge(merged, value.clone());
+    }
+}
+
+fn deserialize(value: toml::Value) -> Result<Config> {
+    value
+        .try_into()
+        .map_err(|e: toml::de::Error| config_err(e.message().to_string()))
+}
+
+/// Record the highest-precedence source of every value
+fn origins(layers: &[(ConfigSource, toml::Value)]) -> BTreeMap<String, ConfigSource> {
+    let mut origins = BTreeMap::new();
+    for (source, value) in layers {
+        for (key, _) in leaves(value) {
+            origins.insert(key, source.clone());
+        }
+    }
+    origins
+}
+
+/// All non-table values in a TOML value, keyed by their dotted path
+pub(super) fn leaves(value: &toml::Value) -> Vec<(String, &toml::Value)> {
+    fn walk<'a>(value: &'a toml::Value, prefix: &str, out: &mut Vec<(String, &'a toml::Value)>) {
+        match value.as_table() {
+            Some(table) => {
+                for (key, child) in table {
+                    let path = if prefix.is_empty() {
+                        key.clone()
+                    } else {
+                        format!("{}.{}", prefix, key)
+                    };
+                    walk(child, &path, out);
+                }
+            }
+            None => out.push((prefix.to_string(), value)),
+        }
+    }
+
+    let mut out = Vec::new();
+    walk(value, "", &mut out);
+    out
+}
@@ -0,0 +1,178 @@
+//! Locations of configuration, data and state files.
+//!
+//! Files follow the XDG base directory layout (or the platform equivalent):
+//! configuration lives in `$XDG_CONFIG_HOME/chris`, persistent data in
+//! `$XDG_DATA_HOME/chris`, state such as history in `$XDG_STATE_HOME/chris` and
+//! cached downloads in `$XDG_CACHE_HOME/chris`.
+//! Older versions kept everything in `~/.chris`; those files are detected and can be
+//! moved with `chris config migrate`.
+//!
+//! Projects can also carry a `.chris.toml` in their root directory, which is found
+//! by walking up from the current directory.
+
+use super::ConfigDocument;
+use crate::error::Result;
+use dirs::home_dir;
+use std::path::{Path, PathBuf};
+
+/// Name of the application directory inside each base directory
+const APP_DIR: &str = "chris";
+
+/// Name of the project-local configuration file
+pub const PROJECT_FILE: &str = ".chris.toml";
+
+/// Marker recording that the legacy notice has been shown
+const LEGACY_NOTICE_MARKER: &str = "legacy-notice-shown";
+
+/// Directory holding the configuration file
+pub fn config_dir() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(APP_DIR)
+}
+
+/// Directory holding persistent user data
+pub fn data_dir() -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(APP_DIR)
+}
+
+/// Directory holding data that can be fetched again, such as the list of models
+pub fn cache_dir() -> PathBuf {
+    dirs::cache_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(APP_DIR)
+}
+
+/// Directory holding state that should persist between runs, such as history
+///
+/// Platforms without a state directory fall back to the local data directory.
+pub fn state_dir() -> PathBuf {
+    dirs::state_dir()
+        .or_else(dirs::data_local_dir)
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(APP_DIR)
+}
+
+/// Find the nearest project configuration file in `start` or one of its parents
+pub fn find_project_file(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(PROJECT_FILE))
+        .find(|path| path.is_file())
+}
+
+/// Directory used by versions that kept everything in `~/.chris`
+pub fn legacy_dir() -> PathBuf {
+    home_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(".chris")
+}
+
+/// Legacy configuration file, if it exists
+pub fn legacy_config_file() -> Option<PathBuf> {
+    Some(legacy_dir().join("config.toml")).filter(|path| path.exists())
+}
+
+/// Return a one-time notice about legacy files that have not been migrated
+///
+/// The notice is only returned the first time legacy files are detected.
+pub fn legacy_notice() -> Option<String> {
+    legacy_config_file()?;
+
+    let marker = state_dir().join(LEGACY_NOTICE_MARKER);
+    if marker.exists() {
+        return None;
+    }
+
+    // Failing to record the marker only means the notice is shown again
+    if std::fs::create_dir_all(state_dir()).is_ok() {
+        let _ = std::fs::write(&marker, "");
+    }
+
+    Some(format!(
+        "Found configuration from an older version in {}. \
+        Run `chris config migrate` to move it to {}.",
+        legacy_dir().display(),
+        config_dir().display()
+    ))
+}
+
+/// Move legacy files into their XDG locations
+///
+/// Files whose destination already exists are left in place. Returns a description
+/// of each action taken.
+pub fn migrate_legacy(config_path: &Path, history_path: &Path) -> Result<Vec<String>> {
+    let legacy = legacy_dir();
+    let legacy_history = legacy.join("history");
+    let mut actions = Vec::new();
+
+    let mut moves = vec![
+        (legacy.join("config.toml"), config_path.to_path_buf()),
+        (legacy_history.clone(), history_path.to_path_buf()),
+    ];
+
+    // Backups made when the legacy config was upgraded travel with it
+    if let (Ok(entries), Some(config_parent)) = (std::fs::read_dir(&legacy), config_path.parent()) {
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with("config.toml.") && name.ends_with(".bak") {
+                moves.push((entry.path(), config_parent.join(name)));
+            }
+        }
+    }
+
+    for (from, to) in moves {
+        if !from.exists() {
+            continue;
+        }
+
+        if to.exists() {
+            actions.push(format!(
+                "Skipped {} because {} already exists",
+                from.display(),
+                to.display()
+            ));
+            continue;
+        }
+
+        if let Some(parent) = to.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        move_file(&from, &to)?;
+        actions.push(format!("Moved {} to {}", from.display(), to.display()));
+    }
+
+    // Configs written by older versions point explicitly at the legacy history file
+    if config_path.exists() {
+        let mut document = ConfigDocument::open(config_path)?;
+        if document.get_str("history_file") == Some(legacy_history.to_string_lossy().as_ref()) {
+            document.set("history_file", &history_path.to_string_lossy())?;
+            document.save()?;
+            actions.push(format!(
+                "Updated history_file in {} to {}",
+                config_path.display(),
+                history_path.display()
+            ));
+        }
+    }
+
+    // Remove the legacy directory once nothing is left in it
+    if legacy.is_dir() && std::fs::read_dir(&legacy)?.next().is_none() {
+        std::fs::remove_dir(&legacy)?;
+        actions.push(format!("Removed empty directory {}", legacy.display()));
+    }
+
+    Ok(actions)
+}
+
+/// Move a file, falling back to copy and delete across file systems
+fn move_file(from: &Path, to: &Path) -> Result<()> {
+    if std::fs::rename(from, to).is_err() {
+        std::fs::copy(from, to)?;
+        std::fs::remove_file(from)?;
+    }
+
+    Ok(())
+}
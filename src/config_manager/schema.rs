@@ -0,0 +1,215 @@
+//! Configuration schema versions and upgrades.
+//!
+//! Each configuration file records the `config_version` it was written for. Older
+//! files are upgraded in place when they are loaded, after a backup copy has been
+//! made next to them.
+
+use super::document::{lookup, with_defaults};
+use super::{ConfigDocument, Profile, Provider};
+use crate::error::{config_err, Result};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// Version of the configuration schema written by this build
+pub const CURRENT_CONFIG_VERSION: u32 = 2;
+
+/// Version assumed for files that predate the `config_version` key
+const UNVERSIONED: u32 = 1;
+
+/// Upgrades a document by one version, returning a description of each change
+type Migration = fn(&mut ConfigDocument) -> Vec<String>;
+
+/// Migrations in order, starting with the one that upgrades from version 1
+const MIGRATIONS: &[Migration] = &[migrate_v1_to_v2];
+
+/// Upgrade a configuration file if needed and warn about keys that are not recognized
+pub(super) fn prepare_file(path: &Path) -> Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+
+    let mut document = ConfigDocument::open(path)?;
+    upgrade(&mut document)?;
+    warn_unknown_keys(&document.to_value()?, path);
+
+    Ok(())
+}
+
+/// Log a warning for each key in a file that the configuration does not recognize
+pub(super) fn warn_unknown_keys(file: &toml::Value, path: &Path) {
+    for (key, suggestion) in unknown_keys(file) {
+        match suggestion {
+            Some(suggestion) => log::warn!(
+                "Unknown configuration key '{}' in {}. Did you mean '{}'?",
+                key,
+                path.display(),
+                suggestion
+            ),
+            None => log::warn!("Unknown configuration key '{}' in {}", key, path.display()),
+        }
+    }
+}
+
+/// Bring a document up to the current schema version, backing up the original file
+fn upgrade(document: &mut ConfigDocument) -> Result<()> {
+    let version = match document
+        .get_item("config_version")
+        .and_then(|item| item.as_integer())
+    {
+        Some(version) => u32::try_from(version)
+            .ok()
+            .filter(|version| *version >= UNVERSIONED)
+            .ok_or_else(|| {
+                config_err(format!(
+                    "{} has an invalid config_version {}; the lowest version is {}",
+                    document.path().display(),
+                    version,
+                    UNVERSIONED
+                ))
+            })?,
+        None => UNVERSIONED,
+    };
+
+    if version > CURRENT_CONFIG_VERSION {
+        log::warn!(
+            "{} was written by a newer version of chris (config version {}); \
+            some settings may be ignored",
+            document.path().display(),
+            version
+        );
+        return Ok(());
+    }
+
+    if version == CURRENT_CONFIG_VERSION {
+        return Ok(());
+    }
+
+    let backup = backup_path(document.path(), version);
+    std::fs::copy(document.path(), &backup)?;
+
+    for migration in &MIGRATIONS[(version - UNVERSIONED) as usize..] {
+        for change in migration(document) {
+            log::info!("{}", change);
+        }
+    }
+    document.set_raw("config_version", CURRENT_CONFIG_VERSION as i64);
+    document.save()?;
+
+    log::info!(
+        "Upgraded {} from config version {} to {} (backup saved to {})",
+        document.path().display(),
+        version,
+        CURRENT_CONFIG_VERSION,
+        backup.display()
+    );
+
+    Ok(())
+}
+
+/// Location of the backup made before upgrading from `version`
+fn backup_path(path: &Path, version: u32) -> PathBuf {
+    let mut backup = OsString::from(path.as_os_str());
+    backup.push(format!(".v{}.bak", version));
+    PathBuf::from(backup)
+}
+
+/// Find keys in a file that the configuration does not recognize
+///
+/// Each key is returned with the closest known key, if one is similar enough.
+pub fn unknown_keys(file: &toml::Value) -> Vec<(String, Option<String>)> {
+    // Keys that survive a round trip through `Config` are the ones it understands.
+    // Invalid files are reported when they are loaded, so there is nothing to add here.
+    let Ok(mut config) = with_defaults(file.clone()) else {
+        return Vec::new();
+    };
+    for profile in config.profiles.values_mut() {
+        *profile = full_profile();
+    }
+    let Ok(known) = toml::Value::try_from(&config) else {
+        return Vec::new();
+    };
+
+    let mut unknown = Vec::new();
+    collect_unknown(file, &known, "", &mut unknown);
+    unknown
+}
+
+/// A profile with every field set, so that all profile keys appear when serialized
+fn full_profile() -> Profile {
+    Profile {
+        provider: Some(Provider::default()),
+        model: Some(String::new()),
+        max_tokens: Some(0),
+        temperature: Some(0.0),
+        top_p: Some(0.0),
+        stop: Some(Vec::new()),
+        seed: Some(0),
+        presence_penalty: Some(0.0),
+        frequency_penalty: Some(0.0),
+        system_prompt: Some(String::new()),
+        enable_personalization: Some(false),
+    }
+}
+
+fn collect_unknown(
+    value: &toml::Value,
+    known: &toml::Value,
+    prefix: &str,
+    unknown: &mut Vec<(String, Option<String>)>,
+) {
+    let Some(table) = value.as_table() else {
+        return;
+    };
+
+    for (key, child) in table {
+        let path = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+
+        if lookup(known, &path).is_some() {
+            collect_unknown(child, known, &path, unknown);
+            continue;
+        }
+
+        let siblings = if prefix.is_empty() {
+            known.as_table()
+        } else {
+            lookup(known, prefix).and_then(toml::Value::as_table)
+        };
+        let suggestion = siblings.and_then(|siblings| {
+            siblings
+                .keys()
+                .map(|candidate| (strsim::levenshtein(key, candidate), candidate))
+                .filter(|(distance, _)| *distance <= 2)
+                .min()
+                .map(|(_, candidate)| candidate.clone())
+        });
+
+        unknown.push((path, suggestion));
+    }
+}
+
+/// Version 1 files may use the generic `api_key`, `model` and `api_url` keys shown in
+/// early documentation; move them to the provider-specific keys.
+fn migrate_v1_to_v2(document: &mut ConfigDocument) -> Vec<String> {
+    let prefix = match document.get_str("provider") {
+        Some("openai") => "openai",
+        _ => "openrouter",
+    };
+
+    let mut renames = vec![
+        ("api_key", format!("{}_api_key", prefix)),
+        ("model", format!("{}_model", prefix)),
+    ];
+    if prefix == "openai" {
+        renames.push(("api_url", "openai_api_url".to_string()));
+    }
+
+    renames
+        .into_iter()
+        .filter(|(from, to)| document.rename_key(from, to))
+        .map(|(from, to)| format!("Renamed configuration key '{}' to '{}'", from, to))
+        .collect()
+}
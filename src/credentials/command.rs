@@ -0,0 +1,64 @@
+use super::{normalize_key, CredentialStore};
+use crate::config_manager::{CredentialsConfig, Provider};
+use crate::error::{credential_err, Result};
+use tokio::process::Command;
+
+/// Reads API keys from the output of a user-supplied command, e.g. `pass show openai`
+///
+/// Only the first line of output is used, matching the convention of password managers
+/// that print the secret followed by metadata.
+#[derive(Debug)]
+pub struct CommandStore {
+    config: CredentialsConfig,
+}
+
+impl CommandStore {
+    /// Create a store using the commands from the credentials configuration
+    pub fn new(config: CredentialsConfig) -> Self {
+        Self { config }
+    }
+}
+
+#[async_trait::async_trait]
+impl CredentialStore for CommandStore {
+    fn name(&self) -> &'static str {
+        "command"
+    }
+
+    async fn get(&self, provider: &Provider) -> Result<Option<String>> {
+        let Some(cmd) = self.config.command(provider) else {
+            return Ok(None);
+        };
+
+        let output = if cfg!(target_os = "windows") {
+            Command::new("cmd").args(["/C", cmd]).output().await
+        } else {
+            Command::new("sh").args(["-c", cmd]).output().await
+        }
+        .map_err(|e| credential_err(format!("Failed to run '{}': {}", cmd, e)))?;
+
+        if !output.status.success() {
+            return Err(credential_err(format!(
+                "'{}' failed ({}): {}",
+                cmd,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        normalize_key(stdout.lines().next().unwrap_or_default()).map(Some)
+    }
+
+    async fn set(&self, provider: &Provider, _key: &str) -> Result<()> {
+        Err(credential_err(format!(
+            "Keys for the command backend are managed by the command itself. \
+            Set credentials.{}_command to a command that prints the key.",
+            provider.as_str()
+        )))
+    }
+
+    async fn delete(&self, provider: &Provider) -> Result<bool> {
+        self.set(provider, "").await.map(|_| false)
+    }
+}
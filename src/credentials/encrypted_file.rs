@@ -0,0 +1,162 @@
+use super::CredentialStore;
+use crate::config_manager::Provider;
+use crate::error::{credential_err, Result};
+use age::secrecy::SecretString;
+use std::collections::BTreeMap;
+use std::io::{IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Environment variable that supplies the passphrase without prompting
+const PASSPHRASE_ENV: &str = "CHRIS_PASSPHRASE";
+
+/// Stores API keys in a file encrypted with an age passphrase
+///
+/// The passphrase is read from `CHRIS_PASSPHRASE` or prompted for once per process.
+pub struct EncryptedFileStore {
+    path: PathBuf,
+    passphrase: Mutex<Option<SecretString>>,
+}
+
+impl EncryptedFileStore {
+    /// Create a store backed by the given file
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            passphrase: Mutex::new(None),
+        }
+    }
+
+    /// Get the passphrase, prompting for it the first time
+    fn passphrase(&self, creating: bool) -> Result<SecretString> {
+        let mut cached = self
+            .passphrase
+            .lock()
+            .map_err(|_| credential_err("Passphrase lock poisoned"))?;
+        if let Some(passphrase) = cached.as_ref() {
+            return Ok(passphrase.clone());
+        }
+
+        let passphrase = match std::env::var(PASSPHRASE_ENV) {
+            Ok(passphrase) => passphrase,
+            Err(_) => {
+                if !std::io::stdin().is_terminal() {
+                    return Err(credential_err(format!(
+                        "A passphrase is required for {}. Set {} when not running interactively.",
+                        self.path.display(),
+                        PASSPHRASE_ENV
+                    )));
+                }
+
+                let passphrase = rpassword::prompt_password(format!(
+                    "Passphrase for {}: ",
+                    self.path.display()
+                ))?;
+                if creating && rpassword::prompt_password("Confirm passphrase: ")? != passphrase {
+                    return Err(credential_err("Passphrases do not match"));
+                }
+                passphrase
+            }
+        };
+
+        if passphrase.is_empty() {
+            return Err(credential_err("Passphrase cannot be empty"));
+        }
+
+        let passphrase = SecretString::from(passphrase);
+        *cached = Some(passphrase.clone());
+        Ok(passphrase)
+    }
+
+    /// Decrypt and parse all stored keys
+    fn read_keys(&self) -> Result<BTreeMap<String, String>> {
+        if !self.path.exists() {
+            return Ok(BTreeMap::new());
+        }
+
+        let encrypted = std::fs::read(&self.path)?;
+        let identity = age::scrypt::Identity::new(self.passphrase(false)?);
+        let decryptor = age::Decryptor::new_buffered(&encrypted[..])
+            .map_err(|e| credential_err(format!("Invalid credentials file: {}", e)))?;
+
+        let mut plaintext = String::new();
+        decryptor
+            .decrypt(std::iter::once(&identity as &dyn age::Identity))
+            .map_err(|e| {
+                credential_err(format!("Failed to decrypt {}: {}", self.path.display(), e))
+            })?
+            .read_to_string(&mut plaintext)?;
+
+        toml::from_str(&plaintext).map_err(|e| {
+            credential_err(format!(
+                "Invalid credentials file {}: {}",
+                self.path.display(),
+                e
+            ))
+        })
+    }
+
+    /// Encrypt and write all keys, readable only by the current user
+    fn write_keys(&self, keys: &BTreeMap<String, String>) -> Result<()> {
+        let passphrase = self.passphrase(!self.path.exists())?;
+        let plaintext = toml::to_string(keys)?;
+
+        let mut encrypted = Vec::new();
+        let mut writer =
+            age::Encryptor::with_user_passphrase(passphrase).wrap_output(&mut encrypted)?;
+        writer.write_all(plaintext.as_bytes())?;
+        writer.finish()?;
+
+        // Write a new file next to the old one and rename it over it, so a failed write
+        // can't lose the keys already stored. Temporary files are created readable only
+        // by the current user.
+        let dir = match self.path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        std::fs::create_dir_all(dir)?;
+        let mut file = tempfile::Builder::new()
+            .prefix(".chris-credentials-")
+            .tempfile_in(dir)?;
+        file.write_all(&encrypted)?;
+        file.as_file().sync_all()?;
+        file.persist(&self.path).map_err(|e| e.error)?;
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for EncryptedFileStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncryptedFileStore")
+            .field("path", &self.path)
+            .finish_non_exhaustive()
+    }
+}
+
+#[async_trait::async_trait]
+impl CredentialStore for EncryptedFileStore {
+    fn name(&self) -> &'static str {
+        "encrypted file"
+    }
+
+    async fn get(&self, provider: &Provider) -> Result<Option<String>> {
+        Ok(self.read_keys()?.remove(provider.as_str()))
+    }
+
+    async fn set(&self, provider: &Provider, key: &str) -> Result<()> {
+        let mut keys = self.read_keys()?;
+        keys.insert(provider.as_str().to_string(), key.to_string());
+        self.write_keys(&keys)
+    }
+
+    async fn delete(&self, provider: &Provider) -> Result<bool> {
+        let mut keys = self.read_keys()?;
+        let removed = keys.remove(provider.as_str()).is_some();
+        if removed {
+            self.write_keys(&keys)?;
+        }
+
+        Ok(removed)
+    }
+}
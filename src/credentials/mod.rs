@@ -0,0 +1,85 @@
+//! Storage for API keys outside the configuration file.
+//!
+//! Keys set in the configuration file or the environment always take precedence.
+//! Otherwise the key for the selected provider is looked up in the configured
+//! [`CredentialBackend`], so it never has to be written to disk in the clear.
+
+mod command;
+mod encrypted_file;
+#[cfg(target_os = "linux")]
+mod secret_service;
+
+pub use command::CommandStore;
+pub use encrypted_file::EncryptedFileStore;
+#[cfg(target_os = "linux")]
+pub use secret_service::SecretServiceStore;
+
+use crate::config_manager::{Config, CredentialBackend, CredentialsConfig, Provider};
+use crate::error::{credential_err, Result};
+
+/// Trait implemented by each credential backend
+#[async_trait::async_trait]
+pub trait CredentialStore: Send + Sync {
+    /// Human-readable name of the backend
+    fn name(&self) -> &'static str;
+
+    /// Look up the API key for a provider
+    async fn get(&self, provider: &Provider) -> Result<Option<String>>;
+
+    /// Store the API key for a provider, replacing any existing key
+    async fn set(&self, provider: &Provider, key: &str) -> Result<()>;
+
+    /// Remove the API key for a provider, returning whether one was stored
+    async fn delete(&self, provider: &Provider) -> Result<bool>;
+}
+
+/// Create the store for the configured backend
+///
+/// Returns `None` for [`CredentialBackend::Config`], where keys only come from the
+/// configuration file and environment.
+pub fn create_store(config: &CredentialsConfig) -> Result<Option<Box<dyn CredentialStore>>> {
+    match config.backend {
+        CredentialBackend::Config => Ok(None),
+        #[cfg(target_os = "linux")]
+        CredentialBackend::SecretService => Ok(Some(Box::new(SecretServiceStore))),
+        #[cfg(not(target_os = "linux"))]
+        CredentialBackend::SecretService => Err(credential_err(
+            "The secret-service backend is only available on Linux",
+        )),
+        CredentialBackend::EncryptedFile => {
+            Ok(Some(Box::new(EncryptedFileStore::new(config.file.clone()))))
+        }
+        CredentialBackend::Command => Ok(Some(Box::new(CommandStore::new(config.clone())))),
+    }
+}
+
+/// Fill in the API key for the selected provider from the credential store
+///
+/// Keys that are already set, from the configuration file or the environment, are
+/// left untouched.
+pub async fn resolve_api_key(config: &mut Config) -> Result<()> {
+    if !config.get_api_key().is_empty() {
+        return Ok(());
+    }
+
+    let Some(store) = create_store(&config.credentials)? else {
+        return Ok(());
+    };
+
+    let provider = config.provider.clone();
+    if let Some(key) = store.get(&provider).await? {
+        config.set_api_key(&provider, key);
+    }
+
+    Ok(())
+}
+
+/// Trim a key read from a prompt, file or command, rejecting empty keys
+pub(crate) fn normalize_key(key: &str) -> Result<String> {
+    let key = key.trim();
+    if key.is_empty() {
+        return Err(credential_err("API key cannot be empty"));
+    }
+
+    Ok(key.to_string())
+}
@@ -0,0 +1,84 @@
+use super::CredentialStore;
+use crate::config_manager::Provider;
+use crate::error::{credential_err, AppError, Result};
+use secret_service::{EncryptionType, SecretService};
+use std::collections::HashMap;
+
+/// Value of the `service` attribute on items created by chris
+const SERVICE: &str = "chris";
+
+/// Stores API keys in the Linux Secret Service (GNOME Keyring, KWallet) over D-Bus
+#[derive(Debug)]
+pub struct SecretServiceStore;
+
+impl SecretServiceStore {
+    /// Attributes identifying the item for a provider
+    fn attributes(provider: &Provider) -> HashMap<&'static str, &'static str> {
+        HashMap::from([("service", SERVICE), ("provider", provider.as_str())])
+    }
+
+    async fn connect<'a>() -> Result<SecretService<'a>> {
+        SecretService::connect(EncryptionType::Dh)
+            .await
+            .map_err(|e| credential_err(format!("Could not connect to the Secret Service: {}", e)))
+    }
+}
+
+impl From<secret_service::Error> for AppError {
+    fn from(err: secret_service::Error) -> Self {
+        credential_err(format!("Secret Service error: {}", err))
+    }
+}
+
+#[async_trait::async_trait]
+impl CredentialStore for SecretServiceStore {
+    fn name(&self) -> &'static str {
+        "secret service"
+    }
+
+    async fn get(&self, provider: &Provider) -> Result<Option<String>> {
+        let service = Self::connect().await?;
+        let items = service.search_items(Self::attributes(provider)).await?;
+
+        let Some(item) = items.unlocked.first().or(items.locked.first()) else {
+            return Ok(None);
+        };
+        item.ensure_unlocked().await?;
+
+        let secret = item.get_secret().await?;
+        String::from_utf8(secret)
+            .map(Some)
+            .map_err(|_| credential_err("Stored API key is not valid UTF-8"))
+    }
+
+    async fn set(&self, provider: &Provider, key: &str) -> Result<()> {
+        let service = Self::connect().await?;
+        let collection = service.get_default_collection().await?;
+        collection.ensure_unlocked().await?;
+        collection
+            .create_item(
+                &format!("chris API key ({})", provider),
+                Self::attributes(provider),
+                key.as_bytes(),
+                true,
+                "text/plain",
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, provider: &Provider) -> Result<bool> {
+        let service = Self::connect().await?;
+        let items = service.search_items(Self::attributes(provider)).await?;
+
+        let mut removed = false;
+        for item in items.unlocked.iter().chain(items.locked.iter()) {
+            item.ensure_unlocked().await?;
+            item.delete().await?;
+            removed = true;
+        }
+
+        Ok(removed)
+    }
+}
@@ -0,0 +1,230 @@
+//! Line editor support for interactive mode.
+//!
+//! Tab completes command names and their first argument, history is offered as a
+//! dimmed hint, and recognized commands are highlighted.
+//!
+//! Input can span several lines: pasted text keeps its newlines, Alt+Enter inserts a
+//! newline, and Enter continues on a new line when the current line ends with an
+//! unescaped backslash or a code block is still open.
+
+use crate::code_blocks;
+use crate::config_manager::Provider;
+use crate::repl::{ArgCompletion, CommandRegistry};
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::{Hinter, HistoryHinter};
+use rustyline::history::DefaultHistory;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{
+    Cmd, CompletionType, Context, Editor, EventHandler, Helper, KeyCode, KeyEvent, Modifiers,
+};
+use std::borrow::Cow;
+use std::rc::Rc;
+
+/// Style of a recognized command name
+const COMMAND_STYLE: &str = "\x1b[1;36m";
+
+/// Style of a hint
+const HINT_STYLE: &str = "\x1b[2m";
+
+/// Resets the style
+const RESET: &str = "\x1b[0m";
+
+/// Create the line editor used by interactive mode
+pub fn create_editor(helper: ReplHelper) -> rustyline::Result<Editor<ReplHelper, DefaultHistory>> {
+    let config = rustyline::Config::builder()
+        .bracketed_paste(true)
+        .completion_type(CompletionType::List)
+        .build();
+    let mut editor = Editor::with_config(config)?;
+    editor.set_helper(Some(helper));
+    editor.bind_sequence(
+        KeyEvent(KeyCode::Enter, Modifiers::ALT),
+        EventHandler::Simple(Cmd::Newline),
+    );
+    Ok(editor)
+}
+
+/// Whether Enter should start a new line instead of submitting the input
+///
+/// A line is continued when it ends with a backslash that isn't itself escaped, so a
+/// message ending in a path like `C:\dir\` can be sent by doubling the last backslash.
+pub fn needs_continuation(input: &str) -> bool {
+    ends_with_continuation(input) || code_blocks::ends_in_open_block(input)
+}
+
+/// Remove the backslashes used to continue lines
+pub fn join_continuations(input: &str) -> String {
+    let mut joined = String::with_capacity(input.len());
+    let mut lines = input.split('\n').peekable();
+    while let Some(line) = lines.next() {
+        match lines.peek() {
+            Some(_) if ends_with_continuation(line) => {
+                joined.push_str(&line[..line.len() - 1]);
+                joined.push('\n');
+            }
+            Some(_) => {
+                joined.push_str(line);
+                joined.push('\n');
+            }
+            None => joined.push_str(line),
+        }
+    }
+    joined
+}
+
+/// Whether `line` ends with an odd number of backslashes
+fn ends_with_continuation(line: &str) -> bool {
+    line.chars().rev().take_while(|c| *c == '\\').count() % 2 == 1
+}
+
+/// Completion, hints and highlighting for the interactive prompt
+pub struct ReplHelper {
+    /// Commands completed after a slash
+    commands: Rc<CommandRegistry>,
+
+    /// Model names offered after `/model`
+    models: Vec<String>,
+
+    /// Profile names offered after `/profile`
+    profiles: Vec<String>,
+
+    /// Template names offered after `/template`
+    templates: Vec<String>,
+
+    /// Paths offered after commands taking a file
+    files: FilenameCompleter,
+
+    /// Suggests the rest of a line from the history
+    hinter: HistoryHinter,
+}
+
+impl ReplHelper {
+    /// Create a helper completing the given commands
+    pub fn new(commands: Rc<CommandRegistry>) -> Self {
+        Self {
+            commands,
+            models: Vec::new(),
+            profiles: Vec::new(),
+            templates: Vec::new(),
+            files: FilenameCompleter::new(),
+            hinter: HistoryHinter {},
+        }
+    }
+
+    /// Replace the model names offered after `/model`
+    pub fn set_models<S: Into<String>>(&mut self, models: impl IntoIterator<Item = S>) {
+        self.models = models.into_iter().map(Into::into).collect();
+    }
+
+    /// Replace the profile names offered after `/profile`
+    pub fn set_profiles<S: Into<String>>(&mut self, profiles: impl IntoIterator<Item = S>) {
+        self.profiles = profiles.into_iter().map(Into::into).collect();
+    }
+
+    /// Replace the template names offered after `/template`
+    pub fn set_templates<S: Into<String>>(&mut self, templates: impl IntoIterator<Item = S>) {
+        self.templates = templates.into_iter().map(Into::into).collect();
+    }
+
+    /// Candidates for the first argument of a command
+    fn arguments(&self, completion: ArgCompletion) -> Vec<String> {
+        match completion {
+            ArgCompletion::Models => self.models.clone(),
+            ArgCompletion::Profiles => self.profiles.clone(),
+            ArgCompletion::Templates => self.templates.clone(),
+            ArgCompletion::Providers => Provider::ALL
+                .iter()
+                .map(|provider| provider.as_str().to_string())
+                .collect(),
+            ArgCompletion::Nothing | ArgCompletion::Paths => Vec::new(),
+        }
+    }
+}
+
+/// Candidates starting with `prefix`
+fn matching(candidates: Vec<String>, prefix: &str) -> Vec<Pair> {
+    candidates
+        .into_iter()
+        .filter(|candidate| candidate.starts_with(prefix))
+        .map(|candidate| Pair {
+            display: candidate.clone(),
+            replacement: candidate,
+        })
+        .collect()
+}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let Some(typed) = line[..pos].strip_prefix('/') else {
+            return Ok((pos, Vec::new()));
+        };
+        let Some((name, arg)) = typed.split_once(' ') else {
+            return Ok((0, matching(self.commands.names(), &line[..pos])));
+        };
+
+        let Some(command) = self.commands.get(name) else {
+            return Ok((pos, Vec::new()));
+        };
+        match command.completion() {
+            ArgCompletion::Paths => self.files.complete(line, pos, ctx),
+            // Only the first argument is completed
+            _ if arg.contains(' ') => Ok((pos, Vec::new())),
+            completion => Ok((pos - arg.len(), matching(self.arguments(completion), arg))),
+        }
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+
+    fn hint(&self, line: &str, pos: usize, ctx: &Context<'_>) -> Option<String> {
+        self.hinter.hint(line, pos, ctx)
+    }
+}
+
+impl Highlighter for ReplHelper {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        let Some(typed) = line.strip_prefix('/') else {
+            return Cow::Borrowed(line);
+        };
+        let end = typed.find(char::is_whitespace).unwrap_or(typed.len()) + 1;
+        if self.commands.get(&line[1..end]).is_none() {
+            return Cow::Borrowed(line);
+        }
+        Cow::Owned(format!(
+            "{}{}{}{}",
+            COMMAND_STYLE,
+            &line[..end],
+            RESET,
+            &line[end..]
+        ))
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(format!("{}{}{}", HINT_STYLE, hint, RESET))
+    }
+
+    fn highlight_char(&self, line: &str, _pos: usize) -> bool {
+        line.starts_with('/')
+    }
+}
+
+impl Validator for ReplHelper {
+    fn validate(&self, ctx: &mut ValidationContext<'_>) -> rustyline::Result<ValidationResult> {
+        Ok(if needs_continuation(ctx.input()) {
+            ValidationResult::Incomplete
+        } else {
+            ValidationResult::Valid(None)
+        })
+    }
+}
+
+impl Helper for ReplHelper {}
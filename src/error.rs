@@ -39,6 +39,18 @@ pub enum AppError {
     #[error("Missing API key")]
     MissingApiKey,
 
+    /// Error storing or retrieving credentials
+    #[error("Credential error: {0}")]
+    Credential(String),
+
+    /// Monthly spending limit reached
+    #[error("Budget exceeded: {0}")]
+    BudgetExceeded(String),
+
+    /// Request cancelled by the user
+    #[error("Request cancelled")]
+    Cancelled,
+
     /// Error from the REPL
     #[error("REPL error: {0}")]
     Repl(String),
@@ -81,6 +93,12 @@ impl From<toml::ser::Error> for AppError {
     }
 }
 
+impl From<toml::de::Error> for AppError {
+    fn from(err: toml::de::Error) -> Self {
+        Self::Config(err.to_string())
+    }
+}
+
 /// Creates a config error with the given message
 #[inline]
 pub fn config_err<S: Into<String>>(msg: S) -> AppError {
@@ -98,3 +116,9 @@ pub fn api_err<S: Into<String>>(msg: S) -> AppError {
 pub fn validation_err<S: Into<String>>(msg: S) -> AppError {
     AppError::Validation(msg.into())
 }
+
+/// Creates a credential error with the given message
+#[inline]
+pub fn credential_err<S: Into<String>>(msg: S) -> AppError {
+    AppError::Credential(msg.into())
+}
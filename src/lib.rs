@@ -9,15 +9,45 @@ pub mod api_client;
 /// Command-line interface and REPL implementation
 pub mod cli;
 
+/// Copying text to the terminal's clipboard
+pub mod clipboard;
+
+/// Menu for running the commands found in a response
+pub mod command_menu;
+
+/// Code blocks in responses and saving them to files
+pub mod code_blocks;
+
 /// Configuration management
 pub mod config_manager;
 
+/// Secure storage for API keys
+pub mod credentials;
+
+/// Line editor support for interactive mode
+pub mod editor;
+
 /// Error handling types and utilities
 pub mod error;
 
 /// System personalization features
 pub mod personalization;
 
+/// Library of reusable prompt templates
+pub mod prompts;
+
+/// Interactive mode and its slash commands
+pub mod repl;
+
+/// Conversation history and transcripts for interactive mode
+pub mod session;
+
+/// Prompt templates
+pub mod template;
+
+/// Token usage and cost accounting
+pub mod usage;
+
 /// Utility functions
 pub mod util;
 
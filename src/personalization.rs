@@ -1,9 +1,12 @@
 use crate::error::Result;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt;
+use std::path::{Path, PathBuf};
 use sysinfo::System;
 
-// TODO: Add system context. This will include the current directory, the current shell, and the current user.
+/// Variables available in system prompt templates
+pub const PROMPT_VARIABLES: &[&str] = &["username", "os", "cwd", "shell", "date", "git_branch"];
 
 /// Represents the user's system context for personalized interactions
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -22,15 +25,36 @@ pub struct UserContext {
 
     /// Host name
     pub hostname: String,
+
+    /// Current working directory
+    pub cwd: PathBuf,
+
+    /// Name of the user's shell
+    pub shell: String,
+
+    /// Git branch checked out in the current directory, if any
+    pub git_branch: Option<String>,
 }
 
 impl fmt::Display for UserContext {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "User Context:\n  Username: {}\n  OS: {} {}\n  Kernel: {}\n  Hostname: {}",
-            self.username, self.os_name, self.os_version, self.kernel_version, self.hostname
-        )
+            "User Context:\n  Username: {}\n  OS: {} {}\n  Kernel: {}\n  Hostname: {}\n  \
+            Directory: {}\n  Shell: {}",
+            self.username,
+            self.os_name,
+            self.os_version,
+            self.kernel_version,
+            self.hostname,
+            self.cwd.display(),
+            self.shell
+        )?;
+        if let Some(branch) = &self.git_branch {
+            write!(f, "\n  Git Branch: {}", branch)?;
+        }
+
+        Ok(())
     }
 }
 
@@ -74,6 +98,11 @@ impl Personalization {
             os_version: System::os_version().unwrap_or_else(|| String::from("Unknown")),
             kernel_version: System::kernel_version().unwrap_or_else(|| String::from("Unknown")),
             hostname: System::host_name().unwrap_or_else(|| String::from("Unknown")),
+            cwd: std::env::current_dir().unwrap_or_default(),
+            shell: current_shell(),
+            git_branch: std::env::current_dir()
+                .ok()
+                .and_then(|dir| git_branch(&dir)),
         });
 
         Ok(())
@@ -144,6 +173,11 @@ impl Personalization {
             output.push_str(&format!("  OS: {} {}\n", ctx.os_name, ctx.os_version));
             output.push_str(&format!("  Kernel: {}\n", ctx.kernel_version));
             output.push_str(&format!("  Hostname: {}\n", ctx.hostname));
+            output.push_str(&format!("  Directory: {}\n", ctx.cwd.display()));
+            output.push_str(&format!("  Shell: {}\n", ctx.shell));
+            if let Some(branch) = &ctx.git_branch {
+                output.push_str(&format!("  Git Branch: {}\n", branch));
+            }
         } else {
             output.push_str("\nNo user context available\n");
         }
@@ -152,3 +186,83 @@ impl Personalization {
         output
     }
 }
+
+/// Values for the variables in [`PROMPT_VARIABLES`]
+///
+/// Everything except the date comes from the user context, so without personalization
+/// the other variables render as `unknown`.
+pub fn prompt_variables(context: Option<&UserContext>) -> BTreeMap<&'static str, String> {
+    let unknown = || String::from("unknown");
+    let mut variables = BTreeMap::new();
+
+    variables.insert("date", chrono::Local::now().format("%Y-%m-%d").to_string());
+    variables.insert(
+        "username",
+        context.map_or_else(unknown, |ctx| ctx.username.clone()),
+    );
+    variables.insert(
+        "os",
+        context.map_or_else(unknown, |ctx| format!("{} {}", ctx.os_name, ctx.os_version)),
+    );
+    variables.insert(
+        "cwd",
+        context.map_or_else(unknown, |ctx| ctx.cwd.display().to_string()),
+    );
+    variables.insert(
+        "shell",
+        context.map_or_else(unknown, |ctx| ctx.shell.clone()),
+    );
+    variables.insert(
+        "git_branch",
+        context
+            .and_then(|ctx| ctx.git_branch.clone())
+            .unwrap_or_else(unknown),
+    );
+
+    variables
+}
+
+/// Name of the shell the user is running
+fn current_shell() -> String {
+    let shell = if cfg!(windows) {
+        std::env::var("COMSPEC")
+    } else {
+        std::env::var("SHELL")
+    };
+
+    shell
+        .ok()
+        .and_then(|path| {
+            Path::new(&path)
+                .file_stem()
+                .map(|name| name.to_string_lossy().into_owned())
+        })
+        .unwrap_or_else(|| String::from("Unknown"))
+}
+
+/// Branch checked out in the git repository containing `dir`
+///
+/// Reads `.git/HEAD` directly rather than running git. A detached HEAD is reported
+/// as its abbreviated commit hash.
+fn git_branch(dir: &Path) -> Option<String> {
+    let dot_git = dir
+        .ancestors()
+        .map(|ancestor| ancestor.join(".git"))
+        .find(|path| path.exists())?;
+
+    // Worktrees and submodules use a `.git` file pointing at the real directory
+    let git_dir = if dot_git.is_file() {
+        let contents = std::fs::read_to_string(&dot_git).ok()?;
+        let target = contents.trim().strip_prefix("gitdir:")?.trim();
+        dot_git.parent()?.join(target)
+    } else {
+        dot_git
+    };
+
+    let head = std::fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+    match head.strip_prefix("ref: refs/heads/") {
+        Some(branch) => Some(branch.to_string()),
+        None => Some(head.chars().take(7).collect()),
+    }
+}
@@ -0,0 +1,293 @@
+//! Library of reusable prompt templates.
+//!
+//! Templates are TOML files in the `prompts` directory next to the configuration file,
+//! named after the template (e.g. `prompts/explain-error.toml`):
+//!
+//! ```toml
+//! description = "Explain a compiler or runtime error"
+//! prompt = "Explain this error and how to fix it:\n\n{{input}}"
+//! ```
+//!
+//! Placeholders use the same `{{name}}` syntax as the system prompt and are filled in
+//! from `--arg name=value`. A few templates are built in; files with the same name
+//! replace them.
+
+use crate::config_manager::config_dir;
+use crate::error::{config_err, validation_err, Result};
+use crate::template;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Argument filled from standard input when it is not given explicitly
+pub const INPUT_ARG: &str = "input";
+
+/// Templates shipped with chris, as (name, description, prompt)
+const BUILTIN_TEMPLATES: &[(&str, &str, &str)] = &[
+    (
+        "explain-error",
+        "Explain an error message and how to fix it",
+        "Explain the following error. Say what caused it and give the most likely fix, \
+        including any commands to run.\n\n{{input}}",
+    ),
+    (
+        "commit-message",
+        "Write a commit message for a diff",
+        "Write a git commit message for the following diff. Use a short imperative \
+        subject line of at most 72 characters, a blank line, then a body explaining \
+        what changed and why. Reply with the message only.\n\n{{input}}",
+    ),
+    (
+        "review-diff",
+        "Review a diff for bugs and style problems",
+        "Review the following diff. List bugs, risky changes and style problems, most \
+        important first, referring to the lines involved. Say so if it looks good.\n\n\
+        {{input}}",
+    ),
+    (
+        "explain-command",
+        "Explain what a shell command does",
+        "Explain what this shell command does, part by part, and mention anything \
+        destructive it might do:\n\n{{command}}",
+    ),
+];
+
+/// Where a template was defined
+#[derive(Debug, Clone, PartialEq)]
+pub enum TemplateSource {
+    /// Shipped with chris
+    Builtin,
+    /// Loaded from a file in the prompt library
+    File(PathBuf),
+}
+
+impl std::fmt::Display for TemplateSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TemplateSource::Builtin => write!(f, "built-in"),
+            TemplateSource::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// A named prompt with placeholders
+#[derive(Debug, Clone)]
+pub struct PromptTemplate {
+    /// Name used to select the template
+    pub name: String,
+
+    /// One-line summary shown when listing templates
+    pub description: String,
+
+    /// Prompt text containing `{{placeholders}}`
+    pub prompt: String,
+
+    /// Where the template was defined
+    pub source: TemplateSource,
+}
+
+/// Contents of a template file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TemplateFile {
+    #[serde(default)]
+    description: String,
+    prompt: String,
+}
+
+impl PromptTemplate {
+    /// Names of the arguments the template expects, in order of first use
+    pub fn arguments(&self) -> Vec<&str> {
+        template::variables(&self.prompt)
+    }
+
+    /// Fill in the template's placeholders
+    ///
+    /// Every argument must be given, and unused arguments are rejected so that typos
+    /// in argument names don't go unnoticed.
+    pub fn render(&self, args: &BTreeMap<String, String>) -> Result<String> {
+        let expected = self.arguments();
+
+        if let Some(missing) = expected.iter().find(|name| !args.contains_key(**name)) {
+            return Err(validation_err(format!(
+                "Template '{}' needs a value for '{}'. Pass it with --arg {}=...",
+                self.name, missing, missing
+            )));
+        }
+        if let Some(unused) = args.keys().find(|name| !expected.contains(&name.as_str())) {
+            return Err(validation_err(format!(
+                "Template '{}' has no argument '{}'. Expected: {}",
+                self.name,
+                unused,
+                if expected.is_empty() {
+                    "none".to_string()
+                } else {
+                    expected.join(", ")
+                }
+            )));
+        }
+
+        let variables = args
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.clone()))
+            .collect();
+        template::render(&self.prompt, &variables)
+    }
+}
+
+/// The built-in templates together with those in the prompt library directory
+#[derive(Debug, Clone)]
+pub struct PromptLibrary {
+    templates: BTreeMap<String, PromptTemplate>,
+}
+
+impl PromptLibrary {
+    /// Load the library from the default directory
+    pub fn load() -> Result<Self> {
+        Self::load_from(&prompts_dir())
+    }
+
+    /// Load the built-in templates and any `*.toml` templates in `dir`
+    pub fn load_from(dir: &Path) -> Result<Self> {
+        let mut templates: BTreeMap<String, PromptTemplate> = BUILTIN_TEMPLATES
+            .iter()
+            .map(|(name, description, prompt)| {
+                (
+                    name.to_string(),
+                    PromptTemplate {
+                        name: name.to_string(),
+                        description: description.to_string(),
+                        prompt: prompt.to_string(),
+                        source: TemplateSource::Builtin,
+                    },
+                )
+            })
+            .collect();
+
+        if dir.is_dir() {
+            for entry in std::fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.extension().and_then(|ext| ext.to_str()) != Some("toml") {
+                    continue;
+                }
+                let Some(name) = path.file_stem().map(|s| s.to_string_lossy().into_owned()) else {
+                    continue;
+                };
+
+                let file: TemplateFile =
+                    toml::from_str(&std::fs::read_to_string(&path)?).map_err(|e| {
+                        config_err(format!("Invalid template {}: {}", path.display(), e))
+                    })?;
+                templates.insert(
+                    name.clone(),
+                    PromptTemplate {
+                        name,
+                        description: file.description,
+                        prompt: file.prompt,
+                        source: TemplateSource::File(path),
+                    },
+                );
+            }
+        }
+
+        Ok(Self { templates })
+    }
+
+    /// Look up a template by name
+    pub fn get(&self, name: &str) -> Result<&PromptTemplate> {
+        self.templates.get(name).ok_or_else(|| {
+            validation_err(format!(
+                "Unknown template '{}'. Available templates: {}",
+                name,
+                self.names().join(", ")
+            ))
+        })
+    }
+
+    /// Names of all templates, in sorted order
+    pub fn names(&self) -> Vec<&str> {
+        self.templates.keys().map(String::as_str).collect()
+    }
+
+    /// All templates, in sorted order
+    pub fn templates(&self) -> impl Iterator<Item = &PromptTemplate> {
+        self.templates.values()
+    }
+}
+
+/// Directory holding user-defined templates
+pub fn prompts_dir() -> PathBuf {
+    config_dir().join("prompts")
+}
+
+/// Split `name=value` template arguments typed on one line
+///
+/// A value runs until the next `name=`, so it can contain spaces and quotes. A value
+/// wrapped in matching quotes has them removed, which allows it to contain `name=`.
+pub fn split_args(line: &str) -> Vec<String> {
+    let line = line.trim();
+    let mut args = Vec::new();
+    let mut start = 0;
+    let mut quote = None;
+    for (i, c) in line.char_indices() {
+        if i < start {
+            continue;
+        }
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if (c == '"' || c == '\'') && line[..i].ends_with('=') => quote = Some(c),
+            None if c.is_whitespace() => {
+                let next = line[i..].trim_start();
+                if starts_with_name(next) {
+                    args.push(unquote(&line[start..i]));
+                    start = line.len() - next.len();
+                }
+            }
+            None => {}
+        }
+    }
+    if start < line.len() {
+        args.push(unquote(&line[start..]));
+    }
+    args
+}
+
+/// Whether `text` starts with `name=`
+fn starts_with_name(text: &str) -> bool {
+    text.split_once('=').is_some_and(|(name, _)| {
+        !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+    })
+}
+
+/// Remove matching quotes around the value of a `name=value` argument
+fn unquote(arg: &str) -> String {
+    let arg = arg.trim_end();
+    if let Some((name, value)) = arg.split_once('=') {
+        for quote in ['"', '\''] {
+            if let Some(inner) = value
+                .strip_prefix(quote)
+                .and_then(|value| value.strip_suffix(quote))
+            {
+                return format!("{}={}", name, inner);
+            }
+        }
+    }
+    arg.to_string()
+}
+
+/// Parse a `name=value` template argument
+pub fn parse_arg(arg: &str) -> Result<(String, String)> {
+    match arg.split_once('=') {
+        Some((name, value)) if !name.trim().is_empty() => {
+            Ok((name.trim().to_string(), value.to_string()))
+        }
+        _ => Err(validation_err(format!(
+            "Invalid template argument '{}'. Expected name=value",
+            arg
+        ))),
+    }
+}
@@ -0,0 +1,375 @@
+//! Interactive mode.
+//!
+//! Lines starting with `/` run a command from the [`CommandRegistry`]; everything
+//! else is sent to the model as the next turn of the conversation.
+
+mod builtin;
+mod commands;
+
+pub use commands::{
+    parse, split_args, ArgCompletion, ArgStyle, CommandRegistry, Flow, Input, SlashCommand,
+};
+
+use crate::api_client::{tokens, ApiClient, ModelCatalog};
+use crate::cli::{print_debug_context, print_usage_footer, send_recorded, send_tracked};
+use crate::config_manager::Config;
+use crate::editor::{self, ReplHelper};
+use crate::error::{validation_err, AppError, Result};
+use crate::personalization::Personalization;
+use crate::prompts::PromptLibrary;
+use crate::session::{CompactionRequest, Session};
+use crate::util;
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::Editor;
+use std::future::Future;
+use std::rc::Rc;
+
+/// Flags from the command line that affect interactive mode
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReplOptions {
+    /// Show the context sent with each query
+    pub debug: bool,
+
+    /// Show token usage after each response
+    pub verbose: bool,
+
+    /// Personalization was requested on the command line
+    pub personalize: bool,
+}
+
+/// State of an interactive session
+pub struct Repl<'a> {
+    /// Client used for queries; commands may replace it
+    pub api_client: ApiClient,
+
+    /// Configuration before any profile or command line overrides
+    pub base_config: &'a Config,
+
+    /// System information sent with queries
+    pub personalization: &'a mut Personalization,
+
+    /// The conversation so far
+    pub session: Session,
+
+    /// Line editor reading the input
+    pub editor: Editor<ReplHelper, DefaultHistory>,
+
+    /// Flags from the command line
+    pub options: ReplOptions,
+
+    /// Commands available at the prompt
+    commands: Rc<CommandRegistry>,
+
+    /// Revision of the session when compacting it automatically last failed
+    failed_compaction: Option<u64>,
+}
+
+impl<'a> Repl<'a> {
+    /// Set up an interactive session
+    pub async fn new(
+        api_client: ApiClient,
+        base_config: &'a Config,
+        personalization: &'a mut Personalization,
+        options: ReplOptions,
+        commands: CommandRegistry,
+    ) -> Result<Self> {
+        let commands = Rc::new(commands);
+        let mut helper = ReplHelper::new(Rc::clone(&commands));
+        helper.set_models(model_names(api_client.config()).await);
+        helper.set_profiles(base_config.profile_names());
+        if let Ok(library) = PromptLibrary::load() {
+            helper.set_templates(library.templates().map(|template| &template.name));
+        }
+        let editor = editor::create_editor(helper)?;
+
+        let session = if api_client.config().store_history {
+            Session::start()
+        } else {
+            Session::unrecorded()
+        };
+
+        Ok(Self {
+            api_client,
+            base_config,
+            personalization,
+            session,
+            editor,
+            options,
+            commands,
+            failed_compaction: None,
+        })
+    }
+
+    /// The commands available at the prompt
+    pub fn commands(&self) -> &CommandRegistry {
+        &self.commands
+    }
+
+    /// Read and handle lines until the user leaves
+    pub async fn run(mut self) -> Result<()> {
+        util::print_header("Chris Interactive Mode");
+        println!("Type your queries and press Enter. Use Ctrl+D or type /exit to quit.");
+        println!("Type /help for available commands.");
+        println!("End a line with \\ or press Alt+Enter to continue on the next line.\n");
+
+        let store_history = self.api_client.config().store_history;
+        let history_file = self.api_client.config().history_file.clone();
+        if store_history {
+            let _ = self.editor.load_history(&history_file);
+        }
+
+        loop {
+            match self.editor.readline("chris> ") {
+                Ok(line) => {
+                    // Indentation is kept for pasted code, but not in front of a command
+                    let trimmed = line.trim();
+                    if trimmed.is_empty() {
+                        continue;
+                    }
+                    let line = if trimmed.starts_with('/') {
+                        trimmed
+                    } else {
+                        line.trim_end()
+                    };
+                    if store_history {
+                        let _ = self.editor.add_history_entry(line);
+                    }
+
+                    match self.handle_line(&editor::join_continuations(line)).await {
+                        Ok(Flow::Continue) => {}
+                        Ok(Flow::Exit) => break,
+                        Err(AppError::Cancelled) => println!("Cancelled."),
+                        Err(e) => eprintln!("Error: {}", e),
+                    }
+                }
+                Err(ReadlineError::Interrupted) => {
+                    println!("Ctrl+C pressed. Press Ctrl+D or type /exit to quit.");
+                }
+                Err(ReadlineError::Eof) => {
+                    println!("Exiting...");
+                    break;
+                }
+                Err(err) => {
+                    eprintln!("Error: {}", err);
+                    break;
+                }
+            }
+        }
+
+        if store_history {
+            if let Err(e) = self.editor.save_history(&history_file) {
+                log::warn!("Failed to save history: {}", e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Run a command or send a query
+    pub async fn handle_line(&mut self, line: &str) -> Result<Flow> {
+        match parse(line)? {
+            Input::Query(query) => {
+                self.send(&query).await;
+                Ok(Flow::Continue)
+            }
+            Input::Command(name, rest) => {
+                let commands = Rc::clone(&self.commands);
+                match commands.get(&name) {
+                    Some(command) => {
+                        let args = split_args(command, &rest)?;
+                        command.run(self, &args).await
+                    }
+                    None => Err(commands.unknown(&name)),
+                }
+            }
+        }
+    }
+
+    /// Send a query as the next turn of the conversation, reporting errors
+    ///
+    /// The query and the response are added to the session when the request succeeds,
+    /// and `true` is returned. Pressing Ctrl+C abandons the request and leaves the
+    /// conversation unchanged.
+    pub async fn send(&mut self, query: &str) -> bool {
+        let debug = self.options.debug || self.personalization.is_debug();
+        if debug {
+            if let Err(e) = print_debug_context(&self.api_client, self.personalization) {
+                eprintln!("Error: {}", e);
+                return false;
+            }
+        }
+
+        match self.auto_compact(query).await {
+            Ok(()) => {}
+            Err(AppError::Cancelled) => {
+                println!("\nRequest cancelled.");
+                return false;
+            }
+            Err(e) => eprintln!("Warning: failed to compact the conversation: {}", e),
+        }
+
+        match cancellable(send_tracked(
+            &self.api_client,
+            self.session.messages(),
+            query,
+            self.personalization,
+            debug,
+        ))
+        .await
+        {
+            Ok(completion) => {
+                util::print_header("Response");
+                println!("{}", util::format_response(&completion.content));
+                if self.options.verbose {
+                    print_usage_footer(&self.api_client, &completion);
+                }
+                let commands = util::extract_commands(&completion.content).len();
+                if commands > 0 {
+                    println!(
+                        "{} command(s) in this response. Type /run to choose which to run.",
+                        commands
+                    );
+                }
+                self.session.push("user", query);
+                self.session.push("assistant", &completion.content);
+                true
+            }
+            Err(AppError::Cancelled) => {
+                println!("\nRequest cancelled.");
+                false
+            }
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Send `query` in place of the last turn
+    ///
+    /// The last turn is put back if the new request doesn't succeed.
+    pub async fn resend(&mut self, query: &str) -> Result<()> {
+        let Some(removed) = self.session.undo() else {
+            return Err(validation_err("There is no earlier message to send again."));
+        };
+        if !self.send(query).await {
+            self.session.restore(removed);
+        }
+        Ok(())
+    }
+
+    /// Use a new client, keeping the conversation
+    pub async fn set_client(&mut self, api_client: ApiClient) {
+        let models = model_names(api_client.config()).await;
+        if let Some(helper) = self.editor.helper_mut() {
+            helper.set_models(models);
+        }
+        self.api_client = api_client;
+    }
+
+    /// Summarize all but the last `keep_turns` turns of the conversation
+    ///
+    /// Returns the request that was summarized, or `None` if there was nothing to
+    /// compact.
+    pub async fn compact(&mut self, keep_turns: usize) -> Result<Option<CompactionRequest>> {
+        let config = self.api_client.config();
+        let model = config.model();
+        let budget = tokens::context_window(model, &config.context_windows)
+            .saturating_sub(config.max_tokens);
+        let Some(request) = self.session.compaction_request(keep_turns, model, budget)? else {
+            return Ok(None);
+        };
+
+        let completion =
+            cancellable(send_recorded(&self.api_client, request.messages.clone())).await?;
+        self.session.apply_summary(&request, &completion.content);
+
+        Ok(Some(request))
+    }
+
+    /// Tell the user that earlier turns were summarized and where the originals are
+    pub fn report_compaction(&self, request: &CompactionRequest) {
+        println!(
+            "Summarized {} earlier turn(s) of the conversation.",
+            request.turns
+        );
+        if request.omitted_turns > 0 {
+            println!(
+                "The oldest {} turn(s) did not fit in the context window and were left out \
+                of the summary.",
+                request.omitted_turns
+            );
+        }
+        if let Some(path) = self.session.transcript() {
+            println!("The full transcript is kept in {}", path.display());
+        }
+    }
+
+    /// Compact the conversation before sending `query` if it has grown past the
+    /// configured share of the context window
+    ///
+    /// After a failed attempt, the conversation is not compacted automatically again
+    /// until it changes, so the same request isn't sent and paid for with every query.
+    async fn auto_compact(&mut self, query: &str) -> Result<()> {
+        let config = self.api_client.config();
+        if config.compaction.threshold <= 0.0
+            || self.session.turns() == 0
+            || self.failed_compaction == Some(self.session.revision())
+        {
+            return Ok(());
+        }
+
+        // Prompts that can't fit at all are reported when the query is sent
+        let Ok(prepared) = self.api_client.prepare_messages(
+            self.session.messages(),
+            query,
+            self.personalization.get_user_context(),
+        ) else {
+            return Ok(());
+        };
+        let available = prepared.context_window.saturating_sub(config.max_tokens);
+        if prepared.dropped_turns == 0
+            && (prepared.prompt_tokens as f64) < config.compaction.threshold * available as f64
+        {
+            return Ok(());
+        }
+
+        let keep_turns = config.compaction.keep_turns;
+        match self.compact(keep_turns).await {
+            Ok(Some(request)) => self.report_compaction(&request),
+            Ok(None) => {}
+            Err(AppError::Cancelled) => return Err(AppError::Cancelled),
+            Err(e) => {
+                self.failed_compaction = Some(self.session.revision());
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Wait for a request, abandoning it when Ctrl+C is pressed
+///
+/// The line editor reads Ctrl+C as a key, so the signal only arrives while a request
+/// is in flight. Dropping the request closes its connection. The handler stays
+/// installed afterwards, which is why [`util::prompt_input`] and
+/// [`util::prompt_yes_no`] read Ctrl+C themselves.
+pub(crate) async fn cancellable<T>(request: impl Future<Output = Result<T>>) -> Result<T> {
+    tokio::select! {
+        result = request => result,
+        Ok(()) = tokio::signal::ctrl_c() => Err(AppError::Cancelled),
+    }
+}
+
+/// Names of the provider's models, for completion
+async fn model_names(config: &Config) -> Vec<String> {
+    match ModelCatalog::load(config, false).await {
+        Ok(catalog) => catalog.ids().map(str::to_string).collect(),
+        Err(e) => {
+            log::debug!("Model names are not available for completion: {}", e);
+            Vec::new()
+        }
+    }
+}
@@ -0,0 +1,813 @@
+//! Commands built into interactive mode.
+
+use super::{ArgCompletion, ArgStyle, CommandRegistry, Flow, Repl, SlashCommand};
+use crate::api_client::ApiClient;
+use crate::cli::{build_api_client, check_model};
+use crate::clipboard::{self, Copied};
+use crate::code_blocks;
+use crate::command_menu::CommandMenu;
+use crate::config_manager::{Config, ConfigSource, Provider};
+use crate::error::{validation_err, AppError, Result};
+use crate::prompts::{self, PromptLibrary};
+use crate::util;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+
+/// Add the built-in commands to a registry
+pub(super) fn register(registry: &mut CommandRegistry) {
+    registry.register(Help);
+    registry.register(Exit);
+    registry.register(Clear);
+    registry.register(Reset);
+    registry.register(Compact);
+    registry.register(Context);
+    registry.register(Debug);
+    registry.register(Model);
+    registry.register(ProviderCommand);
+    registry.register(Profile);
+    registry.register(Template);
+    registry.register(Edit);
+    registry.register(Attach);
+    registry.register(Retry);
+    registry.register(EditLast);
+    registry.register(Undo);
+    registry.register(Branch);
+    registry.register(Copy);
+    registry.register(Save);
+    registry.register(Run);
+}
+
+/// Error showing how a command is used
+fn usage_err(command: &dyn SlashCommand) -> AppError {
+    validation_err(format!("Usage: /{} {}", command.name(), command.usage()))
+}
+
+/// The single optional argument of a command
+fn optional_arg<'a>(command: &dyn SlashCommand, args: &'a [String]) -> Result<Option<&'a str>> {
+    match args {
+        [] => Ok(None),
+        [arg] => Ok(Some(arg)),
+        _ => Err(usage_err(command)),
+    }
+}
+
+/// Build a client from `config` with one key changed
+///
+/// The API key is looked up again, since the provider may have changed.
+async fn reconfigure(config: &Config, key: &str, value: &str, command: &str) -> Result<ApiClient> {
+    let mut config = config.clone();
+    config.apply_override(
+        key,
+        toml::Value::String(value.to_string()),
+        ConfigSource::CommandLine(command.to_string()),
+    )?;
+    build_api_client(config).await
+}
+
+/// Build a client from `config` using another of the provider's models
+async fn with_model(config: &Config, model: &str, command: &str) -> Result<ApiClient> {
+    check_model(config, model).await?;
+    let key = format!("{}_model", config.provider.as_str());
+    reconfigure(config, &key, model, command).await
+}
+
+/// Split the first word, such as a path, off free text
+///
+/// The word can be quoted or, outside Windows, have its spaces escaped with `\\` as
+/// path completion writes them. Returns the word and the text after the whitespace that follows it.
+fn first_word(text: &str) -> (String, &str) {
+    let mut word = String::new();
+    let mut chars = text.char_indices();
+    let mut end = text.len();
+    let quote = text.chars().next().filter(|c| *c == '"' || *c == '\'');
+    if quote.is_some() {
+        chars.next();
+    }
+
+    while let Some((i, c)) = chars.next() {
+        match quote {
+            Some(q) if c == q => {
+                end = i + c.len_utf8();
+                break;
+            }
+            Some(_) => word.push(c),
+            None if c == '\\' && !cfg!(windows) => match chars.next() {
+                Some((_, next)) => word.push(next),
+                None => word.push(c),
+            },
+            None if c.is_whitespace() => {
+                end = i;
+                break;
+            }
+            None => word.push(c),
+        }
+    }
+    (word, text[end..].trim_start())
+}
+
+/// Let the user edit `draft` in their editor
+///
+/// Returns the trimmed text, or `None` if nothing is left.
+fn compose(draft: &str) -> Result<Option<String>> {
+    // Created fresh and private to the user, and removed when dropped
+    let mut file = tempfile::Builder::new()
+        .prefix("chris-draft-")
+        .suffix(".md")
+        .tempfile()?;
+    file.write_all(draft.as_bytes())?;
+    file.flush()?;
+    util::open_in_editor(file.path())?;
+
+    let text = std::fs::read_to_string(file.path())?.trim().to_string();
+    Ok((!text.is_empty()).then_some(text))
+}
+
+/// The first line of a message, shortened to `width` characters
+fn summarize(text: &str, width: usize) -> String {
+    let line = text.lines().next().unwrap_or_default();
+    if line.chars().count() > width || text.lines().nth(1).is_some() {
+        format!("{}...", line.chars().take(width).collect::<String>())
+    } else {
+        line.to_string()
+    }
+}
+
+/// Index of the code block numbered `n` among `count` blocks
+fn block_index(n: &str, count: usize) -> Result<usize> {
+    n.parse::<usize>()
+        .ok()
+        .filter(|n| (1..=count).contains(n))
+        .map(|n| n - 1)
+        .ok_or_else(|| {
+            validation_err(format!(
+                "There is no code block '{}'. The last response has {} code block(s).",
+                n, count
+            ))
+        })
+}
+
+/// The last message the user sent
+fn last_query(repl: &Repl<'_>) -> Result<String> {
+    repl.session
+        .last_query()
+        .map(str::to_string)
+        .ok_or_else(|| validation_err("There is no earlier message to send again."))
+}
+
+/// `/help`
+struct Help;
+
+#[async_trait::async_trait(?Send)]
+impl SlashCommand for Help {
+    fn name(&self) -> &'static str {
+        "help"
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        &["?"]
+    }
+
+    fn summary(&self) -> &'static str {
+        "Show this list of commands"
+    }
+
+    async fn run(&self, repl: &mut Repl<'_>, _args: &[String]) -> Result<Flow> {
+        println!("{}", repl.commands().help());
+        Ok(Flow::Continue)
+    }
+}
+
+/// `/exit`
+struct Exit;
+
+#[async_trait::async_trait(?Send)]
+impl SlashCommand for Exit {
+    fn name(&self) -> &'static str {
+        "exit"
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        &["quit", "q"]
+    }
+
+    fn summary(&self) -> &'static str {
+        "Leave interactive mode"
+    }
+
+    async fn run(&self, _repl: &mut Repl<'_>, _args: &[String]) -> Result<Flow> {
+        Ok(Flow::Exit)
+    }
+}
+
+/// `/clear`
+struct Clear;
+
+#[async_trait::async_trait(?Send)]
+impl SlashCommand for Clear {
+    fn name(&self) -> &'static str {
+        "clear"
+    }
+
+    fn summary(&self) -> &'static str {
+        "Clear the screen"
+    }
+
+    async fn run(&self, _repl: &mut Repl<'_>, _args: &[String]) -> Result<Flow> {
+        print!("\x1B[2J\x1B[1;1H");
+        Ok(Flow::Continue)
+    }
+}
+
+/// `/reset`
+struct Reset;
+
+#[async_trait::async_trait(?Send)]
+impl SlashCommand for Reset {
+    fn name(&self) -> &'static str {
+        "reset"
+    }
+
+    fn summary(&self) -> &'static str {
+        "Clear the conversation history"
+    }
+
+    async fn run(&self, repl: &mut Repl<'_>, _args: &[String]) -> Result<Flow> {
+        repl.session.reset();
+        println!("Conversation history cleared.");
+        Ok(Flow::Continue)
+    }
+}
+
+/// `/compact`
+struct Compact;
+
+#[async_trait::async_trait(?Send)]
+impl SlashCommand for Compact {
+    fn name(&self) -> &'static str {
+        "compact"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[keep]"
+    }
+
+    fn summary(&self) -> &'static str {
+        "Summarize earlier turns, keeping the last [keep] verbatim"
+    }
+
+    async fn run(&self, repl: &mut Repl<'_>, args: &[String]) -> Result<Flow> {
+        let keep = match optional_arg(self, args)? {
+            Some(keep) => keep.parse::<usize>().map_err(|_| {
+                validation_err(format!("Invalid number of turns to keep: '{}'", keep))
+            })?,
+            None => repl.api_client.config().compaction.keep_turns,
+        };
+
+        match repl.compact(keep).await? {
+            Some(request) => repl.report_compaction(&request),
+            None => println!("Nothing to compact yet."),
+        }
+        Ok(Flow::Continue)
+    }
+}
+
+/// `/context`
+struct Context;
+
+#[async_trait::async_trait(?Send)]
+impl SlashCommand for Context {
+    fn name(&self) -> &'static str {
+        "context"
+    }
+
+    fn summary(&self) -> &'static str {
+        "Show the system information sent with queries"
+    }
+
+    async fn run(&self, repl: &mut Repl<'_>, _args: &[String]) -> Result<Flow> {
+        match repl.personalization.get_user_context() {
+            Some(context) => println!("{}", context),
+            None => println!("Personalization is disabled. No context available."),
+        }
+        Ok(Flow::Continue)
+    }
+}
+
+/// `/debug`
+struct Debug;
+
+#[async_trait::async_trait(?Send)]
+impl SlashCommand for Debug {
+    fn name(&self) -> &'static str {
+        "debug"
+    }
+
+    fn summary(&self) -> &'static str {
+        "Toggle showing the context sent with each query"
+    }
+
+    async fn run(&self, repl: &mut Repl<'_>, _args: &[String]) -> Result<Flow> {
+        let debug = !repl.personalization.is_debug();
+        repl.personalization.set_debug(debug);
+        println!("Debug mode {}", if debug { "enabled" } else { "disabled" });
+        Ok(Flow::Continue)
+    }
+}
+
+/// `/model`
+struct Model;
+
+#[async_trait::async_trait(?Send)]
+impl SlashCommand for Model {
+    fn name(&self) -> &'static str {
+        "model"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[name]"
+    }
+
+    fn summary(&self) -> &'static str {
+        "Show or switch the model, keeping the conversation"
+    }
+
+    fn completion(&self) -> ArgCompletion {
+        ArgCompletion::Models
+    }
+
+    async fn run(&self, repl: &mut Repl<'_>, args: &[String]) -> Result<Flow> {
+        let config = repl.api_client.config();
+        let Some(name) = optional_arg(self, args)? else {
+            println!("Model: {} ({})", config.model(), config.provider);
+            return Ok(Flow::Continue);
+        };
+
+        let client = with_model(config, name, "/model").await?;
+        repl.set_client(client).await;
+        println!("Switched to model {}", repl.api_client.config().model());
+        Ok(Flow::Continue)
+    }
+}
+
+/// `/provider`
+struct ProviderCommand;
+
+#[async_trait::async_trait(?Send)]
+impl SlashCommand for ProviderCommand {
+    fn name(&self) -> &'static str {
+        "provider"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[name]"
+    }
+
+    fn summary(&self) -> &'static str {
+        "Show or switch the provider, keeping the conversation"
+    }
+
+    fn completion(&self) -> ArgCompletion {
+        ArgCompletion::Providers
+    }
+
+    async fn run(&self, repl: &mut Repl<'_>, args: &[String]) -> Result<Flow> {
+        let config = repl.api_client.config();
+        let Some(name) = optional_arg(self, args)? else {
+            println!("Provider: {} ({})", config.provider, config.model());
+            let providers: Vec<_> = Provider::ALL.iter().map(Provider::as_str).collect();
+            println!("Available providers: {}", providers.join(", "));
+            return Ok(Flow::Continue);
+        };
+
+        let provider: Provider = name.parse()?;
+        let client = reconfigure(config, "provider", provider.as_str(), "/provider").await?;
+        repl.set_client(client).await;
+        let config = repl.api_client.config();
+        println!("Switched to {} ({})", config.provider, config.model());
+        Ok(Flow::Continue)
+    }
+}
+
+/// `/profile`
+struct Profile;
+
+#[async_trait::async_trait(?Send)]
+impl SlashCommand for Profile {
+    fn name(&self) -> &'static str {
+        "profile"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[name]"
+    }
+
+    fn summary(&self) -> &'static str {
+        "Show or switch the active profile"
+    }
+
+    fn completion(&self) -> ArgCompletion {
+        ArgCompletion::Profiles
+    }
+
+    async fn run(&self, repl: &mut Repl<'_>, args: &[String]) -> Result<Flow> {
+        let Some(name) = optional_arg(self, args)? else {
+            println!(
+                "Active profile: {}",
+                repl.api_client
+                    .config()
+                    .active_profile
+                    .as_deref()
+                    .unwrap_or("none")
+            );
+            let names = repl.base_config.profile_names();
+            if names.is_empty() {
+                println!("No profiles configured.");
+            } else {
+                println!("Available profiles: {}", names.join(", "));
+            }
+            return Ok(Flow::Continue);
+        };
+
+        let config = repl.api_client.config().switch_profile(name)?;
+        let client = build_api_client(config).await?;
+        repl.set_client(client).await;
+        let config = repl.api_client.config();
+        let personalize = repl.options.personalize || config.enable_personalization;
+        println!("Switched to profile '{}' ({})", name, config.model());
+        let flags = config.flags();
+        if !flags.is_empty() {
+            println!("Command line options still apply: {}", flags.join(", "));
+        }
+        repl.personalization.set_enabled(personalize)?;
+        Ok(Flow::Continue)
+    }
+}
+
+/// `/template`
+struct Template;
+
+#[async_trait::async_trait(?Send)]
+impl SlashCommand for Template {
+    fn name(&self) -> &'static str {
+        "template"
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        &["t"]
+    }
+
+    fn usage(&self) -> &'static str {
+        "[name] [arg=value...]"
+    }
+
+    fn summary(&self) -> &'static str {
+        "List prompt templates, or send one"
+    }
+
+    fn completion(&self) -> ArgCompletion {
+        ArgCompletion::Templates
+    }
+
+    fn arg_style(&self) -> ArgStyle {
+        ArgStyle::Raw
+    }
+
+    async fn run(&self, repl: &mut Repl<'_>, args: &[String]) -> Result<Flow> {
+        let library = PromptLibrary::load()?;
+        let Some(line) = args.first() else {
+            for template in library.templates() {
+                println!("  {} - {}", template.name, template.description);
+            }
+            return Ok(Flow::Continue);
+        };
+
+        let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let args = prompts::split_args(rest)
+            .iter()
+            .map(|arg| prompts::parse_arg(arg))
+            .collect::<Result<BTreeMap<_, _>>>()?;
+        let query = library.get(name)?.render(&args)?;
+        repl.send(&query).await;
+        Ok(Flow::Continue)
+    }
+}
+
+/// `/edit`
+struct Edit;
+
+#[async_trait::async_trait(?Send)]
+impl SlashCommand for Edit {
+    fn name(&self) -> &'static str {
+        "edit"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[text]"
+    }
+
+    fn summary(&self) -> &'static str {
+        "Write a message in $EDITOR, starting from [text], and send it"
+    }
+
+    fn arg_style(&self) -> ArgStyle {
+        ArgStyle::Raw
+    }
+
+    async fn run(&self, repl: &mut Repl<'_>, args: &[String]) -> Result<Flow> {
+        match compose(args.first().map_or("", String::as_str))? {
+            Some(message) => {
+                repl.send(&message).await;
+            }
+            None => println!("Nothing to send."),
+        }
+        Ok(Flow::Continue)
+    }
+}
+
+/// `/attach`
+struct Attach;
+
+#[async_trait::async_trait(?Send)]
+impl SlashCommand for Attach {
+    fn name(&self) -> &'static str {
+        "attach"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<path> [message...]"
+    }
+
+    fn summary(&self) -> &'static str {
+        "Send a file, with an optional message about it"
+    }
+
+    fn completion(&self) -> ArgCompletion {
+        ArgCompletion::Paths
+    }
+
+    fn arg_style(&self) -> ArgStyle {
+        ArgStyle::Raw
+    }
+
+    async fn run(&self, repl: &mut Repl<'_>, args: &[String]) -> Result<Flow> {
+        let Some(line) = args.first() else {
+            return Err(usage_err(self));
+        };
+        let (path, message) = first_word(line);
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| validation_err(format!("Failed to read '{}': {}", path, e)))?;
+
+        let mut query = String::new();
+        if !message.is_empty() {
+            query.push_str(message);
+            query.push_str("\n\n");
+        }
+        query.push_str(&format!("{}:\n```\n{}\n```", path, contents.trim_end()));
+        repl.send(&query).await;
+        Ok(Flow::Continue)
+    }
+}
+
+/// `/retry`
+struct Retry;
+
+#[async_trait::async_trait(?Send)]
+impl SlashCommand for Retry {
+    fn name(&self) -> &'static str {
+        "retry"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[--model <name>]"
+    }
+
+    fn summary(&self) -> &'static str {
+        "Send your last message again, optionally to another model"
+    }
+
+    async fn run(&self, repl: &mut Repl<'_>, args: &[String]) -> Result<Flow> {
+        let model = match args {
+            [] => None,
+            [flag, model] if flag == "--model" => Some(model.as_str()),
+            [arg] => Some(
+                arg.strip_prefix("--model=")
+                    .ok_or_else(|| usage_err(self))?,
+            ),
+            _ => return Err(usage_err(self)),
+        };
+        let query = last_query(repl)?;
+
+        let Some(model) = model else {
+            repl.resend(&query).await?;
+            return Ok(Flow::Continue);
+        };
+
+        // The other model only answers this once
+        let client = with_model(repl.api_client.config(), model, "/retry").await?;
+        let original = std::mem::replace(&mut repl.api_client, client);
+        let result = repl.resend(&query).await;
+        repl.api_client = original;
+        result?;
+        Ok(Flow::Continue)
+    }
+}
+
+/// `/edit-last`
+struct EditLast;
+
+#[async_trait::async_trait(?Send)]
+impl SlashCommand for EditLast {
+    fn name(&self) -> &'static str {
+        "edit-last"
+    }
+
+    fn summary(&self) -> &'static str {
+        "Edit your last message in $EDITOR and send it in its place"
+    }
+
+    async fn run(&self, repl: &mut Repl<'_>, _args: &[String]) -> Result<Flow> {
+        match compose(&last_query(repl)?)? {
+            Some(query) => repl.resend(&query).await?,
+            None => println!("Nothing to send."),
+        }
+        Ok(Flow::Continue)
+    }
+}
+
+/// `/undo`
+struct Undo;
+
+#[async_trait::async_trait(?Send)]
+impl SlashCommand for Undo {
+    fn name(&self) -> &'static str {
+        "undo"
+    }
+
+    fn summary(&self) -> &'static str {
+        "Remove your last message and its response"
+    }
+
+    async fn run(&self, repl: &mut Repl<'_>, _args: &[String]) -> Result<Flow> {
+        match repl.session.undo() {
+            Some(removed) => println!("Removed: {}", summarize(&removed[0].content, 60)),
+            None => println!("Nothing to undo."),
+        }
+        Ok(Flow::Continue)
+    }
+}
+
+/// `/branch`
+struct Branch;
+
+#[async_trait::async_trait(?Send)]
+impl SlashCommand for Branch {
+    fn name(&self) -> &'static str {
+        "branch"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[turn]"
+    }
+
+    fn summary(&self) -> &'static str {
+        "List the turns, or continue in a new session from one of them"
+    }
+
+    async fn run(&self, repl: &mut Repl<'_>, args: &[String]) -> Result<Flow> {
+        let Some(turn) = optional_arg(self, args)? else {
+            if repl.session.turns() == 0 {
+                println!("No turns yet.");
+            }
+            for (i, query) in repl.session.queries().enumerate() {
+                println!("  {:>3}  {}", i + 1, summarize(query, 70));
+            }
+            return Ok(Flow::Continue);
+        };
+
+        let turn = turn
+            .parse::<usize>()
+            .map_err(|_| validation_err(format!("Invalid turn number: '{}'", turn)))?;
+        let branch = repl.session.branch(turn).ok_or_else(|| {
+            validation_err(format!(
+                "There is no turn {}. The conversation has {} turn(s).",
+                turn,
+                repl.session.turns()
+            ))
+        })?;
+
+        repl.session = branch;
+        println!("Continuing from turn {} in a new session.", turn);
+        if let Some(path) = repl.session.transcript() {
+            println!("Its transcript is kept in {}", path.display());
+        }
+        Ok(Flow::Continue)
+    }
+}
+
+/// `/copy`
+struct Copy;
+
+#[async_trait::async_trait(?Send)]
+impl SlashCommand for Copy {
+    fn name(&self) -> &'static str {
+        "copy"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[n]"
+    }
+
+    fn summary(&self) -> &'static str {
+        "Copy the last response, or its nth code block, to the clipboard"
+    }
+
+    async fn run(&self, repl: &mut Repl<'_>, args: &[String]) -> Result<Flow> {
+        let block = optional_arg(self, args)?;
+        let Some(response) = repl.session.last_response() else {
+            println!("Nothing to copy yet.");
+            return Ok(Flow::Continue);
+        };
+
+        let (text, what) = match block {
+            None => (response.to_string(), "the last response".to_string()),
+            Some(n) => {
+                let blocks = code_blocks::extract(response);
+                let text = &blocks[block_index(n, blocks.len())?];
+                (text.content.clone(), format!("code block {}", n))
+            }
+        };
+
+        match clipboard::copy(&text)? {
+            Copied::Clipboard => println!("Copied {} to the clipboard.", what),
+            Copied::File(path) => println!(
+                "The clipboard isn't available here; saved {} to {}",
+                what,
+                path.display()
+            ),
+        }
+        Ok(Flow::Continue)
+    }
+}
+
+/// `/save`
+struct Save;
+
+#[async_trait::async_trait(?Send)]
+impl SlashCommand for Save {
+    fn name(&self) -> &'static str {
+        "save"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<n> [path]"
+    }
+
+    fn summary(&self) -> &'static str {
+        "Save code block n of the last response to a file or directory"
+    }
+
+    async fn run(&self, repl: &mut Repl<'_>, args: &[String]) -> Result<Flow> {
+        let (n, path) = match args {
+            [n] => (n, "."),
+            [n, path] => (n, path.as_str()),
+            _ => return Err(usage_err(self)),
+        };
+        let Some(response) = repl.session.last_response() else {
+            println!("Nothing to save yet.");
+            return Ok(Flow::Continue);
+        };
+
+        let blocks = code_blocks::extract(response);
+        let index = block_index(n, blocks.len())?;
+        let names = code_blocks::file_names(&blocks);
+        let path = code_blocks::target_path(&names[index], Path::new(path));
+        if code_blocks::save(&blocks[index], &path)? {
+            println!("Saved code block {} to {}", n, path.display());
+        }
+        Ok(Flow::Continue)
+    }
+}
+
+/// `/run`
+struct Run;
+
+#[async_trait::async_trait(?Send)]
+impl SlashCommand for Run {
+    fn name(&self) -> &'static str {
+        "run"
+    }
+
+    fn summary(&self) -> &'static str {
+        "Choose commands from the last response to run, edit, copy or explain"
+    }
+
+    async fn run(&self, repl: &mut Repl<'_>, _args: &[String]) -> Result<Flow> {
+        let Some(mut menu) = repl.session.last_response().and_then(CommandMenu::new) else {
+            println!("The last response has no commands to run.");
+            return Ok(Flow::Continue);
+        };
+        menu.run(&repl.api_client, repl.personalization).await?;
+        Ok(Flow::Continue)
+    }
+}
@@ -0,0 +1,238 @@
+//! Registry of slash commands.
+
+use super::Repl;
+use crate::error::{validation_err, Result};
+
+/// What the REPL should do after a command has run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    /// Read the next line
+    Continue,
+    /// Leave interactive mode
+    Exit,
+}
+
+/// What the argument of a command is completed with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgCompletion {
+    /// The argument isn't completed
+    Nothing,
+    /// Names of the provider's models
+    Models,
+    /// Supported providers
+    Providers,
+    /// Configured profiles
+    Profiles,
+    /// Prompt templates
+    Templates,
+    /// Files and directories
+    Paths,
+}
+
+/// How the text after a command's name is passed to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgStyle {
+    /// Split into words like a shell would, so values with spaces can be quoted
+    Words,
+    /// Passed as one argument, exactly as typed apart from surrounding whitespace
+    Raw,
+}
+
+/// A command typed as `/name args...` in interactive mode
+///
+/// Arguments are split like a shell would, so values with spaces can be quoted, unless
+/// the command takes free text and asks for it [`Raw`](ArgStyle::Raw).
+#[async_trait::async_trait(?Send)]
+pub trait SlashCommand {
+    /// Name typed after the slash
+    fn name(&self) -> &'static str;
+
+    /// Other names the command can be typed as
+    fn aliases(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Arguments shown in the help, e.g. `[name]`
+    fn usage(&self) -> &'static str {
+        ""
+    }
+
+    /// One-line description shown in the help
+    fn summary(&self) -> &'static str;
+
+    /// What the first argument is completed with
+    fn completion(&self) -> ArgCompletion {
+        ArgCompletion::Nothing
+    }
+
+    /// How the command's arguments are read
+    fn arg_style(&self) -> ArgStyle {
+        ArgStyle::Words
+    }
+
+    /// Run the command
+    async fn run(&self, repl: &mut Repl<'_>, args: &[String]) -> Result<Flow>;
+}
+
+/// The commands available in interactive mode
+#[derive(Default)]
+pub struct CommandRegistry {
+    commands: Vec<Box<dyn SlashCommand>>,
+}
+
+impl CommandRegistry {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a registry holding the built-in commands
+    pub fn builtin() -> Self {
+        let mut registry = Self::new();
+        super::builtin::register(&mut registry);
+        registry
+    }
+
+    /// Add a command
+    ///
+    /// A command whose name or alias is already taken replaces the existing one, so
+    /// built-in commands can be overridden.
+    pub fn register(&mut self, command: impl SlashCommand + 'static) {
+        let names: Vec<&str> = std::iter::once(command.name())
+            .chain(command.aliases().iter().copied())
+            .collect();
+        self.commands.retain(|existing| {
+            !std::iter::once(existing.name())
+                .chain(existing.aliases().iter().copied())
+                .any(|name| names.contains(&name))
+        });
+        self.commands.push(Box::new(command));
+    }
+
+    /// Look up a command by name or alias, ignoring case
+    pub fn get(&self, name: &str) -> Option<&dyn SlashCommand> {
+        self.commands
+            .iter()
+            .find(|command| {
+                command.name().eq_ignore_ascii_case(name)
+                    || command
+                        .aliases()
+                        .iter()
+                        .any(|alias| alias.eq_ignore_ascii_case(name))
+            })
+            .map(Box::as_ref)
+    }
+
+    /// Names of all commands, with their slash, in sorted order
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .commands
+            .iter()
+            .map(|command| format!("/{}", command.name()))
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Help text listing every command
+    pub fn help(&self) -> String {
+        let mut commands: Vec<&dyn SlashCommand> = self.commands.iter().map(Box::as_ref).collect();
+        commands.sort_by_key(|command| command.name());
+
+        let signature = |command: &dyn SlashCommand| {
+            format!("/{} {}", command.name(), command.usage())
+                .trim_end()
+                .to_string()
+        };
+        let width = commands
+            .iter()
+            .map(|command| signature(*command).len())
+            .max()
+            .unwrap_or(0);
+
+        let mut help = String::from("Available commands:\n");
+        for command in commands {
+            let aliases = if command.aliases().is_empty() {
+                String::new()
+            } else {
+                format!(
+                    " (also {})",
+                    command
+                        .aliases()
+                        .iter()
+                        .map(|alias| format!("/{}", alias))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            };
+            help.push_str(&format!(
+                "  {:<width$}  {}{}\n",
+                signature(command),
+                command.summary(),
+                aliases,
+                width = width
+            ));
+        }
+        help.push_str(
+            "\nAnything else is sent to the model. Start a line with // to send \
+            a message that begins with a slash.",
+        );
+        help
+    }
+
+    /// Error for a command that isn't registered, suggesting a close match
+    pub fn unknown(&self, name: &str) -> crate::error::AppError {
+        let suggestion = self
+            .commands
+            .iter()
+            .flat_map(|command| {
+                std::iter::once(command.name()).chain(command.aliases().iter().copied())
+            })
+            .map(|candidate| (strsim::levenshtein(name, candidate), candidate))
+            .filter(|(distance, _)| *distance <= 2)
+            .min()
+            .map(|(_, candidate)| format!(" Did you mean /{}?", candidate))
+            .unwrap_or_default();
+
+        validation_err(format!(
+            "Unknown command '/{}'.{} Type /help for a list of commands.",
+            name, suggestion
+        ))
+    }
+}
+
+/// A line typed at the prompt
+#[derive(Debug, Clone, PartialEq)]
+pub enum Input {
+    /// A slash command with the text after its name
+    Command(String, String),
+    /// Text to send to the model
+    Query(String),
+}
+
+/// Split a line into a command and the text after it, or a query
+pub fn parse(line: &str) -> Result<Input> {
+    if let Some(query) = line.strip_prefix("//") {
+        return Ok(Input::Query(format!("/{}", query)));
+    }
+    let Some(command) = line.strip_prefix('/') else {
+        return Ok(Input::Query(line.to_string()));
+    };
+
+    let (name, rest) = command
+        .split_once(char::is_whitespace)
+        .unwrap_or((command, ""));
+    Ok(Input::Command(name.to_string(), rest.to_string()))
+}
+
+/// The arguments of `command` in `rest`, the text typed after its name
+pub fn split_args(command: &dyn SlashCommand, rest: &str) -> Result<Vec<String>> {
+    let rest = rest.trim();
+    match command.arg_style() {
+        ArgStyle::Raw if rest.is_empty() => Ok(Vec::new()),
+        ArgStyle::Raw => Ok(vec![rest.to_string()]),
+        ArgStyle::Words => shell_words::split(rest).map_err(|e| {
+            validation_err(format!("Invalid arguments for /{}: {}", command.name(), e))
+        }),
+    }
+}
@@ -0,0 +1,376 @@
+//! Conversation state for interactive mode.
+//!
+//! A session holds the messages sent with each query. Every message is also appended
+//! to a transcript in `sessions/<id>.jsonl` in the state directory, so the original
+//! conversation stays available after older turns have been replaced by a summary.
+
+use crate::api_client::{tokens, Message};
+use crate::config_manager::state_dir;
+use crate::error::{validation_err, Result};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Prefix of the message that replaces compacted turns
+pub const SUMMARY_PREFIX: &str = "Summary of the earlier conversation:";
+
+/// Instructions sent with the turns to be summarized
+const SUMMARY_PROMPT: &str = "Summarize the conversation below so that it can replace \
+    it as context for the rest of the conversation. Keep facts about the user's system, \
+    decisions made, commands and file names, and any open questions. Be concise and \
+    reply with the summary only.";
+
+/// Tokens allowed for the blank line between messages in a compaction request
+const ENTRY_SEPARATOR_TOKENS: usize = 1;
+
+/// An entry in a session transcript
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TranscriptEntry {
+    /// A message added to the conversation
+    Message {
+        /// Role of the message sender
+        role: String,
+        /// Content of the message
+        content: String,
+    },
+    /// Earlier turns were replaced by a summary
+    Compacted {
+        /// Number of turns summarized
+        turns: usize,
+        /// The summary that replaced them
+        summary: String,
+    },
+    /// The conversation was cleared
+    Reset,
+    /// The last turn was removed
+    Undo,
+    /// The session continues another one from one of its turns
+    Branched {
+        /// Transcript of the original session
+        from: PathBuf,
+        /// Number of user turns taken from it
+        turn: usize,
+    },
+}
+
+/// Turns selected for compaction and the request that summarizes them
+#[derive(Debug, Clone)]
+pub struct CompactionRequest {
+    /// Messages asking the model for a summary
+    pub messages: Vec<Message>,
+
+    /// Number of user turns replaced by the summary
+    pub turns: usize,
+
+    /// Number of the oldest of those turns left out of the request to fit the
+    /// context window
+    pub omitted_turns: usize,
+
+    /// Index of the first message kept verbatim
+    split: usize,
+}
+
+/// The conversation of an interactive session
+#[derive(Debug, Clone)]
+pub struct Session {
+    /// Messages sent as history with the next query
+    messages: Vec<Message>,
+
+    /// Transcript file, if the session is being recorded
+    transcript: Option<PathBuf>,
+
+    /// Number of changes made to `messages`
+    revision: u64,
+}
+
+impl Session {
+    /// Start a session recorded in the default sessions directory
+    pub fn start() -> Self {
+        Self::recorded(&sessions_dir().join(format!("{}.jsonl", session_id())))
+    }
+
+    /// Start a session recorded at the given path
+    pub fn recorded(path: &Path) -> Self {
+        Self {
+            messages: Vec::new(),
+            transcript: Some(path.to_path_buf()),
+            revision: 0,
+        }
+    }
+
+    /// Start a session that is not recorded
+    pub fn unrecorded() -> Self {
+        Self {
+            messages: Vec::new(),
+            transcript: None,
+            revision: 0,
+        }
+    }
+
+    /// Messages to send as history with the next query
+    pub fn messages(&self) -> &[Message] {
+        &self.messages
+    }
+
+    /// Location of the transcript, if the session is being recorded
+    pub fn transcript(&self) -> Option<&Path> {
+        self.transcript.as_deref()
+    }
+
+    /// A number that changes whenever the messages change
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Number of user turns in the conversation
+    pub fn turns(&self) -> usize {
+        self.messages.iter().filter(|m| m.role == "user").count()
+    }
+
+    /// Add a message to the conversation
+    pub fn push(&mut self, role: &str, content: &str) {
+        self.record(&TranscriptEntry::Message {
+            role: role.to_string(),
+            content: content.to_string(),
+        });
+        self.messages.push(Message {
+            role: role.to_string(),
+            content: content.to_string(),
+        });
+        self.revision += 1;
+    }
+
+    /// The user's messages, oldest first
+    pub fn queries(&self) -> impl Iterator<Item = &str> {
+        self.messages
+            .iter()
+            .filter(|m| m.role == "user")
+            .map(|m| m.content.as_str())
+    }
+
+    /// The user's most recent message
+    pub fn last_query(&self) -> Option<&str> {
+        self.queries().last()
+    }
+
+    /// The most recent response from the model
+    pub fn last_response(&self) -> Option<&str> {
+        self.messages
+            .iter()
+            .rev()
+            .find(|m| m.role == "assistant")
+            .map(|m| m.content.as_str())
+    }
+
+    /// Remove the last turn: the user's message and the reply to it
+    ///
+    /// Returns the removed messages, or `None` if there is no turn to remove.
+    pub fn undo(&mut self) -> Option<Vec<Message>> {
+        let start = self.messages.iter().rposition(|m| m.role == "user")?;
+        self.record(&TranscriptEntry::Undo);
+        self.revision += 1;
+        Some(self.messages.split_off(start))
+    }
+
+    /// Add back messages removed by [`Session::undo`]
+    pub fn restore(&mut self, messages: Vec<Message>) {
+        for message in messages {
+            self.push(&message.role, &message.content);
+        }
+    }
+
+    /// Start a new session continuing this one after its first `turn` turns
+    ///
+    /// The new session is recorded next to this one, and this session's transcript is
+    /// left as it is. Returns `None` if the conversation has fewer turns.
+    pub fn branch(&self, turn: usize) -> Option<Session> {
+        if turn == 0 || turn > self.turns() {
+            return None;
+        }
+        let end = self
+            .messages
+            .iter()
+            .enumerate()
+            .filter(|(_, m)| m.role == "user")
+            .nth(turn)
+            .map_or(self.messages.len(), |(i, _)| i);
+
+        let mut branch = match &self.transcript {
+            Some(path) => {
+                let branch = Session::recorded(&branch_path(path));
+                branch.record(&TranscriptEntry::Branched {
+                    from: path.clone(),
+                    turn,
+                });
+                branch
+            }
+            None => Session::unrecorded(),
+        };
+        branch.restore(self.messages[..end].to_vec());
+        Some(branch)
+    }
+
+    /// Forget the conversation so far; the transcript keeps it
+    pub fn reset(&mut self) {
+        self.record(&TranscriptEntry::Reset);
+        self.messages.clear();
+        self.revision += 1;
+    }
+
+    /// Prepare a request summarizing all but the last `keep_turns` turns
+    ///
+    /// The request has to fit in `budget` tokens of `model`'s context window, so the
+    /// oldest turns are left out of it when the conversation is too long; they are
+    /// still replaced by the summary. Returns `None` when there is nothing older than
+    /// the turns to keep, and an error when not even the newest of them fits.
+    pub fn compaction_request(
+        &self,
+        keep_turns: usize,
+        model: &str,
+        budget: usize,
+    ) -> Result<Option<CompactionRequest>> {
+        let user_turns: Vec<usize> = self
+            .messages
+            .iter()
+            .enumerate()
+            .filter(|(_, m)| m.role == "user")
+            .map(|(i, _)| i)
+            .collect();
+
+        let split = match user_turns.len().checked_sub(keep_turns) {
+            Some(0) | None => return Ok(None),
+            Some(n) if n == user_turns.len() => self.messages.len(),
+            Some(n) => user_turns[n],
+        };
+
+        let system = Message {
+            role: "system".to_string(),
+            content: SUMMARY_PROMPT.to_string(),
+        };
+        let entries: Vec<String> = self.messages[..split]
+            .iter()
+            .map(|m| format!("{}: {}", m.role, m.content))
+            .collect();
+
+        // Add messages from the most recent backwards while they fit, without starting
+        // on a response to a query that was left out
+        let empty = Message {
+            role: "user".to_string(),
+            content: String::new(),
+        };
+        let mut available = budget.saturating_sub(tokens::count_messages(model, [&system, &empty]));
+        let mut start = split;
+        while start > 0 {
+            let cost = tokens::count_text(model, &entries[start - 1]) + ENTRY_SEPARATOR_TOKENS;
+            if cost > available {
+                break;
+            }
+            available -= cost;
+            start -= 1;
+        }
+        while start < split && self.messages[start].role == "assistant" {
+            start += 1;
+        }
+        if start == split {
+            return Err(validation_err(format!(
+                "The earlier turns are too long for {} to summarize",
+                model
+            )));
+        }
+
+        let count_turns =
+            |messages: &[Message]| messages.iter().filter(|m| m.role == "user").count();
+        Ok(Some(CompactionRequest {
+            messages: vec![
+                system,
+                Message {
+                    role: "user".to_string(),
+                    content: entries[start..].join("\n\n"),
+                },
+            ],
+            turns: count_turns(&self.messages[..split]),
+            omitted_turns: count_turns(&self.messages[..start]),
+            split,
+        }))
+    }
+
+    /// Replace the turns selected by `request` with a summary
+    pub fn apply_summary(&mut self, request: &CompactionRequest, summary: &str) {
+        let summary = summary.trim();
+        self.record(&TranscriptEntry::Compacted {
+            turns: request.turns,
+            summary: summary.to_string(),
+        });
+        self.messages.splice(
+            ..request.split,
+            [Message {
+                role: "system".to_string(),
+                content: format!("{}\n{}", SUMMARY_PREFIX, summary),
+            }],
+        );
+        self.revision += 1;
+    }
+
+    /// Append an entry to the transcript
+    ///
+    /// Failing to write the transcript should not interrupt the conversation.
+    fn record(&self, entry: &TranscriptEntry) {
+        let Some(path) = &self.transcript else {
+            return;
+        };
+
+        let result = (|| -> Result<()> {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?;
+            writeln!(file, "{}", serde_json::to_string(entry)?)?;
+            Ok(())
+        })();
+
+        if let Err(e) = result {
+            log::warn!("Failed to write transcript {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Directory holding session transcripts
+pub fn sessions_dir() -> PathBuf {
+    state_dir().join("sessions")
+}
+
+/// Id of a new session: when it started, and the process running it
+///
+/// The process id keeps sessions started in the same second from sharing a transcript.
+fn session_id() -> String {
+    format!(
+        "{}-{}",
+        chrono::Local::now().format("%Y%m%d-%H%M%S"),
+        std::process::id()
+    )
+}
+
+/// An unused transcript path for a branch of the session recorded at `path`
+fn branch_path(path: &Path) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    (1..)
+        .map(|n| path.with_file_name(format!("{}-branch-{}.jsonl", stem, n)))
+        .find(|candidate| !candidate.exists())
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+/// Read the entries of a transcript
+pub fn read_transcript(path: &Path) -> Result<Vec<TranscriptEntry>> {
+    std::fs::read_to_string(path)?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| Ok(serde_json::from_str(line)?))
+        .collect()
+}
